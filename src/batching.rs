@@ -0,0 +1,223 @@
+use mursten::{Backend, Data, Renderer};
+use nalgebra::*;
+use std::collections::BTreeMap;
+
+use super::geometry::Mesh;
+use super::material::Material;
+use super::mesh_renderer::{GetMeshes, MeshesOnly, Submission, WithMaterials};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BatchStats {
+    pub meshes: usize,
+    pub batches: usize,
+    pub rebuilt: usize,
+}
+
+struct Batch {
+    transforms: Vec<Matrix4<f32>>,
+    mesh: Mesh,
+    material: Material,
+}
+
+// Alternative to `MeshRenderer` for scenes with many small meshes. Meshes sharing a
+// `IntoMesh::batch_key` are transformed on the CPU and merged, and each batch reaches the
+// backend as a single mesh with an identity transform.
+//
+// Merged meshes are kept between frames. A batch is only rebuilt when the number of meshes in it
+// or any of their transforms changed, so meshes whose geometry changes in place should also
+// change their batch key, or `invalidate` has to be called.
+pub struct BatchRenderer<M = MeshesOnly> {
+    cache: BTreeMap<u64, Batch>,
+    stats: BatchStats,
+    submission: M,
+}
+
+impl BatchRenderer {
+    pub fn new() -> Self {
+        BatchRenderer {
+            cache: BTreeMap::new(),
+            stats: BatchStats::default(),
+            submission: MeshesOnly,
+        }
+    }
+    // Sends each batch with the material of its meshes, through
+    // `mesh_renderer::backend::RenderMaterialMesh`. Meshes share a batch key when their
+    // materials do, so the material of the first one stands for the whole batch.
+    pub fn with_materials(self) -> BatchRenderer<WithMaterials> {
+        BatchRenderer {
+            cache: self.cache,
+            stats: self.stats,
+            submission: WithMaterials,
+        }
+    }
+}
+
+impl<M> BatchRenderer<M> {
+    pub fn invalidate(&mut self) {
+        self.cache.clear();
+    }
+    // Counters of the last rendered frame.
+    pub fn stats(&self) -> BatchStats {
+        self.stats
+    }
+}
+
+impl<B, D, M> Renderer<B, D> for BatchRenderer<M>
+where
+    D: Data + GetMeshes,
+    B: Backend<D>,
+    M: Submission<B>,
+{
+    fn render(&mut self, backend: &mut B, data: &D) {
+        let mut groups = BTreeMap::new();
+        for mesh in data.mesh_iter() {
+            groups
+                .entry(mesh.batch_key())
+                .or_insert_with(Vec::new)
+                .push(mesh);
+        }
+
+        self.stats = BatchStats::default();
+        let mut cache = BTreeMap::new();
+        for (key, members) in groups {
+            self.stats.meshes += members.len();
+            self.stats.batches += 1;
+            let transforms: Vec<Matrix4<f32>> = members.iter().map(|m| m.transform()).collect();
+            let unchanged = self.cache
+                .get(&key)
+                .map_or(false, |batch| batch.transforms == transforms);
+            let batch = if unchanged {
+                self.cache.remove(&key).unwrap()
+            } else {
+                self.stats.rebuilt += 1;
+                let mut triangles = Vec::new();
+                for (member, transform) in members.iter().zip(transforms.iter()) {
+                    triangles.extend(member.mesh().transform(transform).triangles);
+                }
+                Batch {
+                    transforms,
+                    mesh: Mesh { triangles },
+                    material: members[0].material(),
+                }
+            };
+            self.submission.submit(
+                backend,
+                Matrix4::identity(),
+                batch.mesh.clone(),
+                batch.material.clone(),
+            );
+            cache.insert(key, batch);
+        }
+        // Batches whose key disappeared this frame are dropped
+        self.cache = cache;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::Triangle;
+    use mesh_renderer::IntoMesh;
+    use mock_backend::{Call, MockBackend};
+    use std::vec;
+
+    struct Shape {
+        position: Vector3<f32>,
+        material: Material,
+    }
+
+    impl IntoMesh for Shape {
+        fn transform(&self) -> Matrix4<f32> {
+            Matrix4::new_translation(&self.position)
+        }
+        fn mesh(&self) -> Mesh {
+            Mesh {
+                triangles: vec![Triangle::default()],
+            }
+        }
+        fn material(&self) -> Material {
+            self.material.clone()
+        }
+    }
+
+    struct Shapes {
+        shapes: Vec<Shape>,
+    }
+
+    impl Data for Shapes {}
+
+    impl GetMeshes for Shapes {
+        fn mesh_iter<'a>(&'a self) -> vec::IntoIter<&IntoMesh> {
+            let meshes: Vec<&IntoMesh> = self.shapes.iter().map(|m| m as &IntoMesh).collect();
+            meshes.into_iter()
+        }
+    }
+
+    fn red() -> Material {
+        Material::new().base_color(Vector4::new(1.0, 0.0, 0.0, 1.0))
+    }
+
+    fn blue() -> Material {
+        Material::new().base_color(Vector4::new(0.0, 0.0, 1.0, 1.0))
+    }
+
+    fn shapes() -> Shapes {
+        let shape = |x, material| Shape {
+            position: Vector3::new(x, 0.0, 0.0),
+            material,
+        };
+        Shapes {
+            shapes: vec![shape(0.0, red()), shape(1.0, blue()), shape(2.0, red())],
+        }
+    }
+
+    #[test]
+    fn batches_are_sent_with_their_material() {
+        let mut backend = MockBackend::new();
+        BatchRenderer::new()
+            .with_materials()
+            .render(&mut backend, &shapes());
+
+        let mut materials: Vec<(usize, Material)> = backend
+            .calls()
+            .iter()
+            .filter_map(|c| match *c {
+                Call::QueueRenderMaterial(_, ref mesh, ref material) => {
+                    Some((mesh.triangles.len(), material.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        materials.sort_by_key(|&(triangles, _)| triangles);
+        assert_eq!(materials, vec![(1, blue()), (2, red())]);
+    }
+
+    #[test]
+    fn only_changed_batches_are_rebuilt() {
+        let mut data = shapes();
+        let mut backend = MockBackend::new();
+        let mut renderer = BatchRenderer::new();
+        let stats = |meshes, batches, rebuilt| BatchStats {
+            meshes,
+            batches,
+            rebuilt,
+        };
+
+        renderer.render(&mut backend, &data);
+        assert_eq!(renderer.stats(), stats(3, 2, 2));
+        renderer.render(&mut backend, &data);
+        assert_eq!(renderer.stats(), stats(3, 2, 0));
+
+        data.shapes[1].position.y = 1.0;
+        renderer.render(&mut backend, &data);
+        assert_eq!(renderer.stats(), stats(3, 2, 1));
+
+        data.shapes.pop();
+        renderer.render(&mut backend, &data);
+        assert_eq!(renderer.stats(), stats(2, 2, 1));
+
+        renderer.invalidate();
+        renderer.render(&mut backend, &data);
+        assert_eq!(renderer.stats(), stats(2, 2, 2));
+    }
+}
@@ -0,0 +1,326 @@
+use alga::linear::Transformation;
+use nalgebra::*;
+use std::f32;
+
+use super::geometry::Triangle;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Aabb { min, max }
+    }
+    pub fn empty() -> Self {
+        Aabb {
+            min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+    pub fn from_points<I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = Point3<f32>>,
+    {
+        points.into_iter().fold(Self::empty(), |b, p| b.grow(p))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+    pub fn grow(self, p: Point3<f32>) -> Self {
+        Aabb {
+            min: Point3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z)),
+            max: Point3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z)),
+        }
+    }
+    pub fn union(self, other: Aabb) -> Self {
+        if other.is_empty() {
+            return self;
+        }
+        self.grow(other.min).grow(other.max)
+    }
+    pub fn center(&self) -> Point3<f32> {
+        Point3::from_coordinates((self.min.coords + self.max.coords) / 2.0)
+    }
+    pub fn extents(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let e = self.extents();
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+    pub fn transform(self, m: &Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return self;
+        }
+        Self::from_points(self.corners().iter().map(|c| m.transform_point(c)))
+    }
+    pub fn contains(&self, p: &Point3<f32>) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x && p.y >= self.min.y && p.y <= self.max.y
+            && p.z >= self.min.z && p.z <= self.max.z
+    }
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x && self.min.y <= other.max.y
+            && self.max.y >= other.min.y && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        let c = sphere.center;
+        let closest = Point3::new(
+            c.x.max(self.min.x).min(self.max.x),
+            c.y.max(self.min.y).min(self.max.y),
+            c.z.max(self.min.z).min(self.max.z),
+        );
+        (closest - c).norm_squared() <= sphere.radius * sphere.radius
+    }
+    // Distance along the ray at which it enters the box, if it does so before `max_t`.
+    pub fn ray_hit(&self, ray: &Ray, max_t: f32) -> Option<f32> {
+        let mut t_min = 0.0_f32;
+        let mut t_max = max_t;
+        for axis in 0..3 {
+            let inv = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv;
+            if inv < 0.0 {
+                ::std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Self {
+        Sphere { center, radius }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Ray { origin, direction }
+    }
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+    // Möller-Trumbore. Returns the distance along the ray and the barycentric coordinates of the
+    // hit relative to `v2` and `v3`. Both faces of the triangle are considered.
+    pub fn intersect_triangle(&self, triangle: &Triangle) -> Option<(f32, f32, f32)> {
+        let p1 = triangle.v1.position;
+        let e1 = triangle.v2.position - p1;
+        let e2 = triangle.v3.position - p1;
+        let p = self.direction.cross(&e2);
+        let det = e1.dot(&p);
+        if det.abs() < 1e-8 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = self.origin - p1;
+        let u = s.dot(&p) * inv_det;
+        if u < 0.0 || u > 1.0 {
+            return None;
+        }
+        let q = s.cross(&e1);
+        let v = self.direction.dot(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&q) * inv_det;
+        if t < 0.0 {
+            return None;
+        }
+        Some((t, u, v))
+    }
+}
+
+// Points `p` with `normal.dot(p) + distance >= 0` are in front of the plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    pub fn new(normal: Vector3<f32>, distance: f32) -> Self {
+        Plane { normal, distance }
+    }
+    pub fn from_point_normal(point: Point3<f32>, normal: Vector3<f32>) -> Self {
+        let normal = normal.normalize();
+        Plane {
+            normal,
+            distance: -normal.dot(&point.coords),
+        }
+    }
+    pub fn signed_distance(&self, p: &Point3<f32>) -> f32 {
+        self.normal.dot(&p.coords) + self.distance
+    }
+    pub fn ray_hit(&self, ray: &Ray) -> Option<f32> {
+        let denominator = self.normal.dot(&ray.direction);
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = -self.signed_distance(&ray.origin) / denominator;
+        if t >= 0.0 {
+            Some(t)
+        } else {
+            None
+        }
+    }
+    fn from_coefficients(a: f32, b: f32, c: f32, d: f32) -> Self {
+        let length = Vector3::new(a, b, c).norm();
+        if length == 0.0 {
+            // Far plane of an infinite projection, everything is on its inner side
+            return Plane {
+                normal: Vector3::zeros(),
+                distance: if d < 0.0 { -f32::INFINITY } else { f32::INFINITY },
+            };
+        }
+        Plane {
+            normal: Vector3::new(a, b, c) / length,
+            distance: d / length,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Extracts the planes of the clipping volume of a `projection * view` matrix. Volumes
+    // described by `Frustum` always point their normals inwards.
+    pub fn from_matrix(m: &Matrix4<f32>) -> Self {
+        let row = |r: usize| [m[(r, 0)], m[(r, 1)], m[(r, 2)], m[(r, 3)]];
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let plane = |a: [f32; 4], sign: f32| {
+            Plane::from_coefficients(
+                r3[0] + sign * a[0],
+                r3[1] + sign * a[1],
+                r3[2] + sign * a[2],
+                r3[3] + sign * a[3],
+            )
+        };
+        Frustum {
+            planes: [
+                plane(r0, 1.0),
+                plane(r0, -1.0),
+                plane(r1, 1.0),
+                plane(r1, -1.0),
+                plane(r2, 1.0),
+                plane(r2, -1.0),
+            ],
+        }
+    }
+    pub fn contains_point(&self, p: &Point3<f32>) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(p) >= 0.0)
+    }
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(&sphere.center) >= -sphere.radius)
+    }
+    // Conservative: boxes near the corners of the frustum may be reported as intersecting.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let n = plane.normal;
+            let positive = Point3::new(
+                if n.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if n.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if n.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(&positive) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::{Camera, Orthographic, Perspective};
+
+    // Linear congruential generator, so every run checks the same points.
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+        fn point(&mut self, size: f32) -> Point3<f32> {
+            let mut coordinate = || (self.next() * 2.0 - 1.0) * size;
+            Point3::new(coordinate(), coordinate(), coordinate())
+        }
+    }
+
+    fn in_clip_volume(m: &Matrix4<f32>, p: &Point3<f32>) -> bool {
+        let c = m * p.to_homogeneous();
+        c.w > 0.0 && c.x.abs() <= c.w && c.y.abs() <= c.w && c.z.abs() <= c.w
+    }
+
+    #[test]
+    fn frustum_planes_bound_the_clip_volume() {
+        let mut random = Random(3);
+        let lenses = [
+            Camera::new(Perspective::new().fov(1.2).aspect(1.5).near(0.5).far(40.0)),
+            Camera::new(Orthographic::extents(-8.0, 8.0, -4.0, 4.0).near(1.0).far(30.0)),
+        ];
+        for camera in lenses.iter() {
+            let view = Matrix4::look_at_rh(&random.point(5.0), &Point3::origin(), &Vector3::y());
+            let m = camera.projection() * view;
+            let frustum = Frustum::from_matrix(&m);
+            let mut inside = 0;
+            for _ in 0..2000 {
+                let p = random.point(30.0);
+                // Points too close to a plane may land on either side through rounding
+                let margin = frustum.planes.iter().map(|plane| plane.signed_distance(&p).abs());
+                if margin.fold(f32::MAX, f32::min) < 1e-3 {
+                    continue;
+                }
+                assert_eq!(frustum.contains_point(&p), in_clip_volume(&m, &p));
+                inside += in_clip_volume(&m, &p) as usize;
+
+                // Never reports a box with a corner inside as outside
+                let aabb = Aabb::new(p, p + random.point(3.0).coords.abs());
+                if aabb.corners().iter().any(|c| in_clip_volume(&m, c)) {
+                    assert!(frustum.intersects_aabb(&aabb));
+                }
+            }
+            assert!(inside > 20, "too few points inside to tell");
+        }
+    }
+}
@@ -0,0 +1,525 @@
+use nalgebra::*;
+use std::f32;
+
+use super::bounds::{Aabb, Frustum, Ray, Sphere};
+use super::geometry::{Mesh, Triangle};
+
+const MAX_LEAF_SIZE: usize = 4;
+const SAH_BINS: usize = 12;
+const TRAVERSAL_COST: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub t: f32,
+    pub index: usize,
+    pub u: f32,
+    pub v: f32,
+}
+
+// Hierarchy over the triangles of a single mesh. Indices returned by the queries refer to
+// `MeshBvh::triangle`, which keeps the order of the source mesh.
+pub struct MeshBvh {
+    tree: Tree,
+    triangles: Vec<Triangle>,
+}
+
+impl MeshBvh {
+    pub fn new(mesh: &Mesh) -> Self {
+        let bounds: Vec<Aabb> = mesh.triangles.iter().map(|t| t.bounds()).collect();
+        MeshBvh {
+            tree: Tree::build(&bounds),
+            triangles: mesh.triangles.clone(),
+        }
+    }
+    pub fn bounds(&self) -> Aabb {
+        self.tree.bounds()
+    }
+    pub fn triangle(&self, index: usize) -> &Triangle {
+        &self.triangles[index]
+    }
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+    pub fn raycast(&self, ray: &Ray, max_t: f32) -> Option<RayHit> {
+        let triangles = &self.triangles;
+        let mut closest: Option<RayHit> = None;
+        self.tree.traverse_ray(ray, max_t, |index, best| {
+            match ray.intersect_triangle(&triangles[index]) {
+                Some((t, u, v)) if t < best => {
+                    closest = Some(RayHit { t, index, u, v });
+                    Some(t)
+                }
+                _ => None,
+            }
+        });
+        closest
+    }
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        let triangles = &self.triangles;
+        self.tree.query(|b| b.intersects(aabb), |i| triangles[i].bounds().intersects(aabb))
+    }
+    pub fn query_sphere(&self, sphere: &Sphere) -> Vec<usize> {
+        let triangles = &self.triangles;
+        self.tree.query(
+            |b| b.intersects_sphere(sphere),
+            |i| triangles[i].bounds().intersects_sphere(sphere),
+        )
+    }
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        let triangles = &self.triangles;
+        self.tree.query(
+            |b| frustum.intersects_aabb(b),
+            |i| frustum.intersects_aabb(&triangles[i].bounds()),
+        )
+    }
+}
+
+// Hierarchy over the bounds of arbitrary objects. Moving objects report their new bounds through
+// `set_bounds` and a call to `refit` brings the hierarchy up to date without changing its shape.
+// When objects moved a lot since the hierarchy was built, `rebuild` gives tighter nodes.
+pub struct ObjectBvh<T> {
+    tree: Tree,
+    objects: Vec<T>,
+    bounds: Vec<Aabb>,
+}
+
+impl<T> ObjectBvh<T> {
+    pub fn new(objects: Vec<(T, Aabb)>) -> Self {
+        let (objects, bounds): (Vec<T>, Vec<Aabb>) = objects.into_iter().unzip();
+        ObjectBvh {
+            tree: Tree::build(&bounds),
+            objects,
+            bounds,
+        }
+    }
+    pub fn bounds(&self) -> Aabb {
+        self.tree.bounds()
+    }
+    pub fn get(&self, index: usize) -> &T {
+        &self.objects[index]
+    }
+    pub fn get_mut(&mut self, index: usize) -> &mut T {
+        &mut self.objects[index]
+    }
+    pub fn object_bounds(&self, index: usize) -> Aabb {
+        self.bounds[index]
+    }
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+    pub fn set_bounds(&mut self, index: usize, bounds: Aabb) {
+        self.bounds[index] = bounds;
+    }
+    pub fn refit(&mut self) {
+        self.tree.refit(&self.bounds);
+    }
+    pub fn rebuild(&mut self) {
+        self.tree = Tree::build(&self.bounds);
+    }
+    // Objects whose bounds are crossed by the ray, closest first.
+    pub fn query_ray(&self, ray: &Ray, max_t: f32) -> Vec<(usize, f32)> {
+        let bounds = &self.bounds;
+        let mut hits = Vec::new();
+        self.tree.traverse_ray(ray, max_t, |index, _| {
+            if let Some(t) = bounds[index].ray_hit(ray, max_t) {
+                hits.push((index, t));
+            }
+            None
+        });
+        hits.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        hits
+    }
+    // Like `query_ray` but `hit` tells the exact distance to each candidate object, so only the
+    // closest one is kept and farther subtrees are skipped.
+    pub fn raycast<F>(&self, ray: &Ray, max_t: f32, mut hit: F) -> Option<(usize, f32)>
+    where
+        F: FnMut(&T, &Ray) -> Option<f32>,
+    {
+        let objects = &self.objects;
+        let mut closest = None;
+        self.tree.traverse_ray(ray, max_t, |index, best| match hit(&objects[index], ray) {
+            Some(t) if t < best => {
+                closest = Some((index, t));
+                Some(t)
+            }
+            _ => None,
+        });
+        closest
+    }
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        let bounds = &self.bounds;
+        self.tree.query(|b| b.intersects(aabb), |i| bounds[i].intersects(aabb))
+    }
+    pub fn query_sphere(&self, sphere: &Sphere) -> Vec<usize> {
+        let bounds = &self.bounds;
+        self.tree.query(
+            |b| b.intersects_sphere(sphere),
+            |i| bounds[i].intersects_sphere(sphere),
+        )
+    }
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        let bounds = &self.bounds;
+        self.tree.query(
+            |b| frustum.intersects_aabb(b),
+            |i| frustum.intersects_aabb(&bounds[i]),
+        )
+    }
+}
+
+// Nodes are stored depth first: the left child of a branch always follows it, the right child is
+// at `right`. This way children come after their parents and refitting is a single reverse pass.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    first: usize,
+    count: usize,
+    right: usize,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+impl Tree {
+    fn build(bounds: &[Aabb]) -> Self {
+        let centroids: Vec<Point3<f32>> = bounds.iter().map(|b| b.center()).collect();
+        let mut tree = Tree {
+            nodes: Vec::new(),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            tree.subdivide(bounds, &centroids, 0, bounds.len());
+        }
+        tree
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::empty(), |n| n.bounds)
+    }
+
+    fn subdivide(
+        &mut self,
+        bounds: &[Aabb],
+        centroids: &[Point3<f32>],
+        first: usize,
+        count: usize,
+    ) -> usize {
+        let node_bounds = self.indices[first..first + count]
+            .iter()
+            .fold(Aabb::empty(), |b, &i| b.union(bounds[i]));
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            first,
+            count,
+            right: 0,
+        });
+        if count <= MAX_LEAF_SIZE {
+            return index;
+        }
+
+        let (axis, min, extent, split_bin) =
+            match find_split(&self.indices[first..first + count], bounds, centroids, &node_bounds) {
+                Some(split) => split,
+                None => return index,
+            };
+
+        // Partition the indices so that the ones falling in the left bins come first
+        let mut mid = first;
+        for i in first..first + count {
+            if bin_of(centroids[self.indices[i]][axis], min, extent) <= split_bin {
+                self.indices.swap(i, mid);
+                mid += 1;
+            }
+        }
+        if mid == first || mid == first + count {
+            return index;
+        }
+
+        self.nodes[index].count = 0;
+        self.subdivide(bounds, centroids, first, mid - first);
+        let right = self.subdivide(bounds, centroids, mid, first + count - mid);
+        self.nodes[index].right = right;
+        index
+    }
+
+    fn refit(&mut self, bounds: &[Aabb]) {
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            self.nodes[i].bounds = if node.is_leaf() {
+                self.indices[node.first..node.first + node.count]
+                    .iter()
+                    .fold(Aabb::empty(), |b, &j| b.union(bounds[j]))
+            } else {
+                self.nodes[i + 1].bounds.union(self.nodes[node.right].bounds)
+            };
+        }
+    }
+
+    fn query<F, G>(&self, overlaps: F, accept: G) -> Vec<usize>
+    where
+        F: Fn(&Aabb) -> bool,
+        G: Fn(usize) -> bool,
+    {
+        let mut result = Vec::new();
+        if self.nodes.is_empty() {
+            return result;
+        }
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if !overlaps(&node.bounds) {
+                continue;
+            }
+            if node.is_leaf() {
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    if accept(i) {
+                        result.push(i);
+                    }
+                }
+            } else {
+                stack.push(node.right);
+                stack.push(n + 1);
+            }
+        }
+        result
+    }
+
+    // `hit` receives each candidate index and the closest distance found so far, and returns the
+    // distance of a closer hit if there is one. Children are visited nearest first.
+    fn traverse_ray<F>(&self, ray: &Ray, max_t: f32, mut hit: F)
+    where
+        F: FnMut(usize, f32) -> Option<f32>,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut best = max_t;
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node.bounds.ray_hit(ray, best).is_none() {
+                continue;
+            }
+            if node.is_leaf() {
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    if let Some(t) = hit(i, best) {
+                        best = t;
+                    }
+                }
+            } else {
+                let left = self.nodes[n + 1].bounds.ray_hit(ray, best);
+                let right = self.nodes[node.right].bounds.ray_hit(ray, best);
+                match (left, right) {
+                    (Some(l), Some(r)) if r < l => {
+                        stack.push(n + 1);
+                        stack.push(node.right);
+                    }
+                    (Some(_), Some(_)) => {
+                        stack.push(node.right);
+                        stack.push(n + 1);
+                    }
+                    (Some(_), None) => stack.push(n + 1),
+                    (None, Some(_)) => stack.push(node.right),
+                    (None, None) => {}
+                }
+            }
+        }
+    }
+}
+
+fn bin_of(value: f32, min: f32, extent: f32) -> usize {
+    let bin = ((value - min) / extent * SAH_BINS as f32) as usize;
+    bin.min(SAH_BINS - 1)
+}
+
+// Binned surface area heuristic. Returns the axis, the centroid range along it and the last bin
+// of the left side, or `None` when keeping the node as a leaf is cheaper.
+fn find_split(
+    indices: &[usize],
+    bounds: &[Aabb],
+    centroids: &[Point3<f32>],
+    node_bounds: &Aabb,
+) -> Option<(usize, f32, f32, usize)> {
+    let centroid_bounds = Aabb::from_points(indices.iter().map(|&i| centroids[i]));
+    let node_area = node_bounds.surface_area();
+    let mut best: Option<(usize, f32, f32, usize)> = None;
+    let mut best_cost = indices.len() as f32;
+
+    for axis in 0..3 {
+        let min = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - min;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bin_bounds = [Aabb::empty(); SAH_BINS];
+        let mut bin_counts = [0; SAH_BINS];
+        for &i in indices {
+            let b = bin_of(centroids[i][axis], min, extent);
+            bin_bounds[b] = bin_bounds[b].union(bounds[i]);
+            bin_counts[b] += 1;
+        }
+
+        // Areas and counts of everything right of each split, swept from the end
+        let mut right_area = [0.0; SAH_BINS];
+        let mut right_count = [0; SAH_BINS];
+        let mut accumulated = Aabb::empty();
+        let mut count = 0;
+        for b in (1..SAH_BINS).rev() {
+            accumulated = accumulated.union(bin_bounds[b]);
+            count += bin_counts[b];
+            right_area[b] = accumulated.surface_area();
+            right_count[b] = count;
+        }
+
+        let mut accumulated = Aabb::empty();
+        let mut count = 0;
+        for b in 0..SAH_BINS - 1 {
+            accumulated = accumulated.union(bin_bounds[b]);
+            count += bin_counts[b];
+            if count == 0 || right_count[b + 1] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + (accumulated.surface_area() * count as f32
+                    + right_area[b + 1] * right_count[b + 1] as f32)
+                    / node_area.max(f32::EPSILON);
+            if cost < best_cost {
+                best_cost = cost;
+                best = Some((axis, min, extent, b));
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::{Camera, Perspective};
+    use geometry::Vertex;
+
+    // Linear congruential generator, so every run checks the same scenes.
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+        fn between(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * self.next()
+        }
+        fn point(&mut self, size: f32) -> Point3<f32> {
+            Point3::new(
+                self.between(-size, size),
+                self.between(-size, size),
+                self.between(-size, size),
+            )
+        }
+    }
+
+    fn random_mesh(random: &mut Random, count: usize) -> Mesh {
+        let triangles = (0..count)
+            .map(|_| {
+                let center = random.point(10.0);
+                let mut corner = || Vertex::at(center + random.point(1.0).coords);
+                Triangle::new(corner(), corner(), corner())
+            })
+            .collect();
+        Mesh { triangles }
+    }
+
+    fn sorted(mut indices: Vec<usize>) -> Vec<usize> {
+        indices.sort();
+        indices
+    }
+
+    fn matching<F: Fn(&Aabb) -> bool>(bounds: &[Aabb], accept: F) -> Vec<usize> {
+        (0..bounds.len()).filter(|&i| accept(&bounds[i])).collect()
+    }
+
+    #[test]
+    fn mesh_queries_match_a_brute_force_scan() {
+        let mut random = Random(7);
+        let mesh = random_mesh(&mut random, 300);
+        let bvh = MeshBvh::new(&mesh);
+        let bounds: Vec<Aabb> = mesh.triangles.iter().map(|t| t.bounds()).collect();
+
+        let mut hits = 0;
+        for _ in 0..100 {
+            let origin = random.point(30.0);
+            let ray = Ray::new(origin, random.point(10.0) - origin);
+            let expected = (0..mesh.triangles.len())
+                .filter_map(|i| ray.intersect_triangle(&mesh.triangles[i]).map(|h| (i, h.0)))
+                .filter(|&(_, t)| t < 100.0)
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            let hit = bvh.raycast(&ray, 100.0).map(|hit| (hit.index, hit.t));
+            assert_eq!(hit, expected);
+            hits += hit.iter().count();
+
+            let corner = random.point(10.0);
+            let aabb = Aabb::new(corner, corner + Vector3::repeat(random.between(0.5, 6.0)));
+            let expected = matching(&bounds, |b| b.intersects(&aabb));
+            assert_eq!(sorted(bvh.query_aabb(&aabb)), expected);
+
+            let sphere = Sphere::new(random.point(10.0), random.between(0.5, 6.0));
+            let expected = matching(&bounds, |b| b.intersects_sphere(&sphere));
+            assert_eq!(sorted(bvh.query_sphere(&sphere)), expected);
+        }
+        assert!(hits > 10, "too few rays hit anything to tell");
+
+        let lens = Perspective::new().fov(0.6).near(1.0).far(25.0);
+        for _ in 0..20 {
+            let eye = random.point(20.0);
+            let view = Matrix4::look_at_rh(&eye, &random.point(5.0), &Vector3::y());
+            let frustum = Frustum::from_matrix(&(Camera::new(lens).projection() * view));
+            let expected = matching(&bounds, |b| frustum.intersects_aabb(b));
+            assert_eq!(sorted(bvh.query_frustum(&frustum)), expected);
+        }
+    }
+
+    #[test]
+    fn refitted_objects_match_a_brute_force_scan() {
+        let mut random = Random(11);
+        let boxes = |random: &mut Random| {
+            let corner = random.point(10.0);
+            Aabb::new(corner, corner + random.point(1.0).coords.abs())
+        };
+        let objects: Vec<(usize, Aabb)> = (0..200).map(|i| (i, boxes(&mut random))).collect();
+        let mut bvh = ObjectBvh::new(objects);
+        for i in (0..bvh.len()).filter(|i| i % 3 == 0) {
+            let moved = boxes(&mut random);
+            bvh.set_bounds(i, moved);
+        }
+        bvh.refit();
+        let bounds: Vec<Aabb> = (0..bvh.len()).map(|i| bvh.object_bounds(i)).collect();
+
+        for _ in 0..100 {
+            let corner = random.point(10.0);
+            let aabb = Aabb::new(corner, corner + Vector3::repeat(random.between(0.5, 6.0)));
+            let expected = matching(&bounds, |b| b.intersects(&aabb));
+            assert_eq!(sorted(bvh.query_aabb(&aabb)), expected);
+
+            let sphere = Sphere::new(random.point(10.0), random.between(0.5, 6.0));
+            let expected = matching(&bounds, |b| b.intersects_sphere(&sphere));
+            assert_eq!(sorted(bvh.query_sphere(&sphere)), expected);
+
+            let origin = random.point(30.0);
+            let ray = Ray::new(origin, random.point(10.0) - origin);
+            let mut expected: Vec<(usize, f32)> = (0..bounds.len())
+                .filter_map(|i| bounds[i].ray_hit(&ray, 100.0).map(|t| (i, t)))
+                .collect();
+            expected.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            assert_eq!(bvh.query_ray(&ray, 100.0), expected);
+        }
+    }
+}
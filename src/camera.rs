@@ -1,12 +1,60 @@
 use mursten::{Backend, Data, Updater};
 use nalgebra::*;
+use std::vec;
 
+use super::bounds::{Plane, Ray};
+use super::input::{self, ResizeEvent};
+
+// The projection matrix used to be the public `projection` field. It is now built from the lens
+// by `projection()`, and a camera with a given matrix is made with `Camera::new(matrix)`.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Camera {
-    pub projection: Matrix4<f32>,
+    pub lens: Lens,
+}
+
+// How the camera projects what it sees. The parameters are kept so they can be changed later,
+// the matrix is built from them on every `Camera::projection` call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lens {
+    Perspective(Perspective),
+    Orthographic(Orthographic),
+    // Any other projection, given directly as a matrix.
+    Matrix(Matrix4<f32>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Perspective {
+    // Vertical field of view, in radians.
+    pub fov: f32,
+    // Width over height.
+    pub aspect: f32,
+    pub near: f32,
+    // `None` puts the far plane at infinity.
+    pub far: Option<f32>,
+    // Maps the near plane to depth 1 and the far plane to depth -1, and depth tests keep the
+    // greater depth. Meant for GPU backends storing depth as floats from 0 to 1, where it spreads
+    // the precision evenly over the distance, above all with an infinite far plane. The renderers
+    // of this crate only flip their depth test, it does not change their precision.
+    pub reversed_z: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orthographic {
+    pub left: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub top: f32,
+    pub near: f32,
+    pub far: f32,
 }
 
 pub trait GetCamera {
     fn get_camera<'a>(&'a self) -> (Matrix4<f32>, &'a Camera);
+    // For data that may have no camera yet, like an `ecs::World`. Updaters and renderers call
+    // this one, and skip the camera when there is none.
+    fn find_camera<'a>(&'a self) -> Option<(Matrix4<f32>, &'a Camera)> {
+        Some(self.get_camera())
+    }
 }
 
 pub struct CameraUpdater {}
@@ -23,29 +71,756 @@ where
     B: Backend<D> + backend::SetCamera,
 {
     fn update(&mut self, backend: &mut B, data: &mut D) {
-        let (transform, camera) = data.get_camera();
-        backend.set_camera(transform, camera);
+        if let Some((transform, camera)) = data.find_camera() {
+            backend.set_camera(transform, camera);
+        }
+    }
+}
+
+// Area of the output a camera draws into, in fractions of the output size. The origin is the top
+// left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Viewport {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+    pub fn full() -> Self {
+        Self::new(0.0, 0.0, 1.0, 1.0)
+    }
+    // Pixel rectangle as `(x, y, width, height)` for an output of the given size.
+    pub fn pixels(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let (w, h) = (width as f32, height as f32);
+        let x0 = (self.x * w).round().max(0.0).min(w) as usize;
+        let y0 = (self.y * h).round().max(0.0).min(h) as usize;
+        let x1 = ((self.x + self.width) * w).round().max(0.0).min(w) as usize;
+        let y1 = ((self.y + self.height) * h).round().max(0.0).min(h) as usize;
+        (x0, y0, x1.saturating_sub(x0), y1.saturating_sub(y0))
+    }
+    // Aspect ratio the camera needs for the picture not to look stretched.
+    pub fn aspect(&self, width: usize, height: usize) -> f32 {
+        (self.width * width as f32) / (self.height * height as f32)
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+// One of several cameras drawing into the same output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewportCamera {
+    pub name: String,
+    // The view matrix, like the one returned by `GetCamera::get_camera`.
+    pub transform: Matrix4<f32>,
+    pub camera: Camera,
+    pub viewport: Viewport,
+    // Cameras are drawn from the lowest order to the highest, so later ones are drawn over
+    // earlier ones where their viewports overlap.
+    pub order: i32,
+    pub enabled: bool,
+    // When set, `AspectUpdater` keeps the lens in shape with the viewport.
+    pub tracking: Option<AspectTracking>,
+}
+
+impl ViewportCamera {
+    pub fn new(name: &str, camera: Camera) -> Self {
+        ViewportCamera {
+            name: name.to_string(),
+            transform: Matrix4::identity(),
+            camera,
+            viewport: Viewport::full(),
+            order: 0,
+            enabled: true,
+            tracking: None,
+        }
+    }
+    pub fn transform(self, transform: Matrix4<f32>) -> Self {
+        Self { transform, ..self }
+    }
+    pub fn viewport(self, viewport: Viewport) -> Self {
+        Self { viewport, ..self }
+    }
+    pub fn order(self, order: i32) -> Self {
+        Self { order, ..self }
+    }
+    // Takes the current lens as the designed one, see `AspectTracking`.
+    pub fn track_aspect(self, policy: AspectPolicy) -> Self {
+        Self {
+            tracking: Some(AspectTracking::new(self.camera.lens, policy)),
+            ..self
+        }
+    }
+}
+
+pub trait GetCameras {
+    // Enabled cameras, in render order.
+    fn camera_iter<'a>(&'a self) -> vec::IntoIter<&'a ViewportCamera>;
+}
+
+// Named collection of cameras, for split screen, picture in picture or debug views.
+//
+//     let mut cameras = Cameras::new();
+//     let left = Viewport::new(0.0, 0.0, 0.5, 1.0);
+//     let right = Viewport::new(0.5, 0.0, 0.5, 1.0);
+//     cameras.add(ViewportCamera::new("left", Camera::perspective()).viewport(left));
+//     cameras.add(ViewportCamera::new("right", Camera::perspective()).viewport(right));
+//     cameras.get_mut("left").unwrap().transform = player_one.view();
+#[derive(Debug, Clone, Default)]
+pub struct Cameras {
+    cameras: Vec<ViewportCamera>,
+}
+
+impl Cameras {
+    pub fn new() -> Self {
+        Cameras {
+            cameras: Vec::new(),
+        }
+    }
+    // Replaces any camera with the same name.
+    pub fn add(&mut self, camera: ViewportCamera) {
+        self.remove(&camera.name.clone());
+        self.cameras.push(camera);
+    }
+    pub fn remove(&mut self, name: &str) -> Option<ViewportCamera> {
+        let index = self.cameras.iter().position(|c| c.name == name)?;
+        Some(self.cameras.remove(index))
+    }
+    pub fn get(&self, name: &str) -> Option<&ViewportCamera> {
+        self.cameras.iter().find(|c| c.name == name)
+    }
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut ViewportCamera> {
+        self.cameras.iter_mut().find(|c| c.name == name)
+    }
+    pub fn len(&self) -> usize {
+        self.cameras.len()
+    }
+}
+
+impl GetCameras for Cameras {
+    fn camera_iter<'a>(&'a self) -> vec::IntoIter<&'a ViewportCamera> {
+        let mut cameras: Vec<&ViewportCamera> = self.cameras.iter().filter(|c| c.enabled).collect();
+        // Stable, so cameras with the same order keep the order in which they were added
+        cameras.sort_by_key(|c| c.order);
+        cameras.into_iter()
+    }
+}
+
+// Sends every camera with its viewport to the backend, in render order.
+pub struct ViewportUpdater {}
+
+impl ViewportUpdater {
+    pub fn new() -> Self {
+        ViewportUpdater {}
+    }
+}
+
+impl<B, D> Updater<B, D> for ViewportUpdater
+where
+    D: Data + GetCameras,
+    B: Backend<D> + backend::SetViewport,
+{
+    fn update(&mut self, backend: &mut B, data: &mut D) {
+        for camera in data.camera_iter() {
+            backend.set_viewport(camera.transform, &camera.camera, camera.viewport);
+        }
+    }
+}
+
+// What to do when a viewport does not have the aspect ratio a lens was designed for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AspectPolicy {
+    // Keeps the lens as it is, so the picture gets stretched.
+    Stretch,
+    // Everything the lens was designed to show stays visible, and more is shown along the
+    // dimension with room to spare.
+    Fit,
+    // The viewport is covered by what the lens was designed to show, and the excess along one
+    // dimension is cut out.
+    Fill,
+}
+
+// A lens as designed, with the aspect ratio of its parameters, and how to adapt it to viewports
+// of any other aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AspectTracking {
+    pub lens: Lens,
+    pub policy: AspectPolicy,
+}
+
+impl AspectTracking {
+    pub fn new<L: Into<Lens>>(lens: L, policy: AspectPolicy) -> Self {
+        AspectTracking {
+            lens: lens.into(),
+            policy,
+        }
+    }
+
+    // The designed lens adapted to a viewport of the given aspect ratio. Perspective lenses keep
+    // the vertical or the horizontal field of view and orthographic ones keep the height or the
+    // width, around the same center. Lenses given as a matrix are left alone.
+    pub fn lens_for(&self, aspect: f32) -> Lens {
+        if self.policy == AspectPolicy::Stretch || !(aspect > 0.0) || !aspect.is_finite() {
+            return self.lens;
+        }
+        let fit = self.policy == AspectPolicy::Fit;
+        match self.lens {
+            Lens::Perspective(p) => {
+                // How much taller the view has to be to keep the designed width
+                let ratio = p.aspect / aspect;
+                let scale = if fit { ratio.max(1.0) } else { ratio.min(1.0) };
+                let fov = 2.0 * ((p.fov / 2.0).tan() * scale).atan();
+                Lens::Perspective(p.fov(fov).aspect(aspect))
+            }
+            Lens::Orthographic(o) => {
+                let (width, height) = (o.right - o.left, o.top - o.bottom);
+                let height = if fit {
+                    height.max(width / aspect)
+                } else {
+                    height.min(width / aspect)
+                };
+                let (x, y) = ((o.left + o.right) / 2.0, (o.bottom + o.top) / 2.0);
+                let (half_width, half_height) = (height * aspect / 2.0, height / 2.0);
+                Lens::Orthographic(Orthographic {
+                    left: x - half_width,
+                    right: x + half_width,
+                    bottom: y - half_height,
+                    top: y + half_height,
+                    ..o
+                })
+            }
+            Lens::Matrix(_) => self.lens,
+        }
+    }
+}
+
+pub trait TrackAspect {
+    // Cameras whose lens follows the shape of their viewport, along with how each one was
+    // designed and the viewport it draws into.
+    fn tracked_cameras<'a>(&'a mut self) -> Vec<(&'a mut Camera, &'a AspectTracking, Viewport)>;
+    // Every resize event `AspectUpdater` takes from the backend, for data that also needs the
+    // size of the output.
+    fn resized(&mut self, _: ResizeEvent) {}
+}
+
+impl TrackAspect for Cameras {
+    fn tracked_cameras<'a>(&'a mut self) -> Vec<(&'a mut Camera, &'a AspectTracking, Viewport)> {
+        let mut tracked = Vec::new();
+        for camera in self.cameras.iter_mut() {
+            if let Some(ref tracking) = camera.tracking {
+                tracked.push((&mut camera.camera, tracking, camera.viewport));
+            }
+        }
+        tracked
+    }
+}
+
+// Listens to the resize events of the backend and adapts the lens of the tracked cameras to the
+// new size of their viewports. Cameras are adapted on every update once the size is known, so
+// cameras added later are also kept in shape. Any change made to their lens is overwritten;
+// change `AspectTracking::lens` instead.
+//
+// The resize events are drained from the backend, so no other updater gets them. They are all
+// passed on to `TrackAspect::resized`, where the data can keep the size for everything else.
+pub struct AspectUpdater {
+    size: Option<ResizeEvent>,
+}
+
+impl AspectUpdater {
+    pub fn new() -> Self {
+        AspectUpdater { size: None }
+    }
+}
+
+impl<B, D> Updater<B, D> for AspectUpdater
+where
+    D: Data + TrackAspect,
+    B: Backend<D> + input::backend::ResizeEventSource,
+{
+    fn update(&mut self, backend: &mut B, data: &mut D) {
+        for event in backend.drain_events() {
+            data.resized(event);
+            self.size = Some(event);
+        }
+        let size = match self.size {
+            Some(size) => size,
+            None => return,
+        };
+        for (camera, tracking, viewport) in data.tracked_cameras() {
+            let aspect = viewport.aspect(size.width, size.height) * size.pixel_aspect;
+            camera.lens = tracking.lens_for(aspect);
+        }
     }
 }
 
 impl Camera {
+    pub fn new<L: Into<Lens>>(lens: L) -> Self {
+        Camera { lens: lens.into() }
+    }
     pub fn orthographic() -> Self {
-        Camera {
-            projection: Orthographic3::new(-1.0, 1.0, -1.0, 1.0, 10.0, 900.0).to_homogeneous(),
-        }
+        Self::new(Orthographic::default())
     }
     pub fn perspective() -> Self {
-        Camera {
-            projection: Perspective3::new(1.0, 1.17, 0.1, 900.0).to_homogeneous(),
+        Self::new(Perspective::default())
+    }
+
+    pub fn projection(&self) -> Matrix4<f32> {
+        match self.lens {
+            Lens::Perspective(ref p) => p.matrix(),
+            Lens::Orthographic(ref o) => o.matrix(),
+            Lens::Matrix(m) => m,
+        }
+    }
+
+    pub fn reversed_z(&self) -> bool {
+        match self.lens {
+            Lens::Perspective(ref p) => p.reversed_z,
+            _ => false,
+        }
+    }
+
+    // Orthographic cameras keep their height and center. Has no effect on matrix lenses.
+    pub fn set_aspect(&mut self, aspect: f32) {
+        match self.lens {
+            Lens::Perspective(ref mut p) => p.aspect = aspect,
+            Lens::Orthographic(ref mut o) => {
+                let center = (o.left + o.right) / 2.0;
+                let half_width = (o.top - o.bottom) * aspect / 2.0;
+                o.left = center - half_width;
+                o.right = center + half_width;
+            }
+            Lens::Matrix(_) => {}
+        }
+    }
+
+    pub fn aspect(&self) -> Option<f32> {
+        match self.lens {
+            Lens::Perspective(ref p) => Some(p.aspect),
+            Lens::Orthographic(ref o) => Some(o.aspect()),
+            Lens::Matrix(_) => None,
         }
     }
 }
 
+// Conversions between world and screen coordinates. `transform` is the view matrix, as returned
+// by `GetCamera::get_camera`. Normalized device coordinates go from -1 to 1 with y pointing up,
+// while pixel coordinates start at the top left corner of a `viewport` sized area and grow right
+// and down.
+impl Camera {
+    // `None` for points behind the camera. Points outside of the view volume are still projected,
+    // with coordinates beyond the -1..1 range.
+    pub fn project_ndc(
+        &self,
+        transform: &Matrix4<f32>,
+        point: &Point3<f32>,
+    ) -> Option<Point3<f32>> {
+        let clip = self.projection() * transform * point.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+        Some(Point3::new(clip.x / clip.w, clip.y / clip.w, clip.z / clip.w))
+    }
+
+    pub fn project(
+        &self,
+        transform: &Matrix4<f32>,
+        point: &Point3<f32>,
+        viewport: Vector2<f32>,
+    ) -> Option<Point2<f32>> {
+        self.project_ndc(transform, point).map(|ndc| ndc_to_pixel(&ndc, viewport))
+    }
+
+    pub fn unproject_ndc(
+        &self,
+        transform: &Matrix4<f32>,
+        ndc: &Point3<f32>,
+    ) -> Option<Point3<f32>> {
+        let inverse = (self.projection() * transform).try_inverse()?;
+        let world = inverse * ndc.to_homogeneous();
+        if world.w.abs() < 1e-12 {
+            return None;
+        }
+        Some(Point3::new(world.x / world.w, world.y / world.w, world.z / world.w))
+    }
+
+    // Ray from the near plane through the pixel, with a unit direction.
+    pub fn screen_ray(
+        &self,
+        transform: &Matrix4<f32>,
+        pixel: &Point2<f32>,
+        viewport: Vector2<f32>,
+    ) -> Option<Ray> {
+        let ndc = pixel_to_ndc(pixel, viewport);
+        let near_z = if self.reversed_z() { 1.0 } else { -1.0 };
+        // Depth 0 lies between near and far, and stays finite with an infinite far plane
+        let near = self.unproject_ndc(transform, &Point3::new(ndc.x, ndc.y, near_z))?;
+        let middle = self.unproject_ndc(transform, &Point3::new(ndc.x, ndc.y, 0.0))?;
+        let direction = middle - near;
+        if direction.norm_squared() == 0.0 {
+            return None;
+        }
+        Some(Ray::new(near, direction.normalize()))
+    }
+
+    // Point under the pixel that is `distance` units in front of the camera, measured along the
+    // viewing direction.
+    pub fn unproject(
+        &self,
+        transform: &Matrix4<f32>,
+        pixel: &Point2<f32>,
+        viewport: Vector2<f32>,
+        distance: f32,
+    ) -> Option<Point3<f32>> {
+        let ray = self.screen_ray(transform, pixel, viewport)?;
+        let inverse = transform.try_inverse()?;
+        let eye = Point3::new(inverse[(0, 3)], inverse[(1, 3)], inverse[(2, 3)]);
+        let forward = -Vector3::new(inverse[(0, 2)], inverse[(1, 2)], inverse[(2, 2)]).normalize();
+        let along = ray.direction.dot(&forward);
+        if along <= 0.0 {
+            return None;
+        }
+        let start = (ray.origin - eye).dot(&forward);
+        Some(ray.at((distance - start) / along))
+    }
+
+    // Where the pixel falls on the plane, for instance the ground under the mouse.
+    pub fn unproject_onto_plane(
+        &self,
+        transform: &Matrix4<f32>,
+        pixel: &Point2<f32>,
+        viewport: Vector2<f32>,
+        plane: &Plane,
+    ) -> Option<Point3<f32>> {
+        let ray = self.screen_ray(transform, pixel, viewport)?;
+        plane.ray_hit(&ray).map(|t| ray.at(t))
+    }
+}
+
+pub fn ndc_to_pixel(ndc: &Point3<f32>, viewport: Vector2<f32>) -> Point2<f32> {
+    Point2::new(
+        (ndc.x + 1.0) / 2.0 * viewport.x,
+        (1.0 - ndc.y) / 2.0 * viewport.y,
+    )
+}
+
+pub fn pixel_to_ndc(pixel: &Point2<f32>, viewport: Vector2<f32>) -> Point2<f32> {
+    Point2::new(
+        pixel.x / viewport.x * 2.0 - 1.0,
+        1.0 - pixel.y / viewport.y * 2.0,
+    )
+}
+
+impl Default for Perspective {
+    fn default() -> Self {
+        Perspective {
+            fov: 1.17,
+            aspect: 1.0,
+            near: 0.1,
+            far: Some(900.0),
+            reversed_z: false,
+        }
+    }
+}
+
+impl Perspective {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn fov(self, fov: f32) -> Self {
+        Self { fov, ..self }
+    }
+    pub fn aspect(self, aspect: f32) -> Self {
+        Self { aspect, ..self }
+    }
+    pub fn near(self, near: f32) -> Self {
+        Self { near, ..self }
+    }
+    pub fn far(self, far: f32) -> Self {
+        Self {
+            far: Some(far),
+            ..self
+        }
+    }
+    pub fn infinite(self) -> Self {
+        Self { far: None, ..self }
+    }
+    pub fn reversed_z(self, reversed_z: bool) -> Self {
+        Self { reversed_z, ..self }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let f = 1.0 / (self.fov / 2.0).tan();
+        let n = self.near;
+        let (c, d) = match self.far {
+            Some(far) => ((far + n) / (n - far), 2.0 * far * n / (n - far)),
+            None => (-1.0, -2.0 * n),
+        };
+        let (c, d) = if self.reversed_z { (-c, -d) } else { (c, d) };
+        let mut m = Matrix4::zeros();
+        m[(0, 0)] = f / self.aspect;
+        m[(1, 1)] = f;
+        m[(2, 2)] = c;
+        m[(2, 3)] = d;
+        m[(3, 2)] = -1.0;
+        m
+    }
+}
+
+impl Default for Orthographic {
+    fn default() -> Self {
+        Orthographic::extents(-1.0, 1.0, -1.0, 1.0)
+    }
+}
+
+impl Orthographic {
+    pub fn extents(left: f32, right: f32, bottom: f32, top: f32) -> Self {
+        Orthographic {
+            left,
+            right,
+            bottom,
+            top,
+            near: 10.0,
+            far: 900.0,
+        }
+    }
+    // Centered on the view axis, `height` units tall and `height * aspect` wide.
+    pub fn size(height: f32, aspect: f32) -> Self {
+        let (half_width, half_height) = (height * aspect / 2.0, height / 2.0);
+        Self::extents(-half_width, half_width, -half_height, half_height)
+    }
+    pub fn near(self, near: f32) -> Self {
+        Self { near, ..self }
+    }
+    pub fn far(self, far: f32) -> Self {
+        Self { far, ..self }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        (self.right - self.left) / (self.top - self.bottom)
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Orthographic3::new(
+            self.left,
+            self.right,
+            self.bottom,
+            self.top,
+            self.near,
+            self.far,
+        ).to_homogeneous()
+    }
+}
+
+impl From<Perspective> for Lens {
+    fn from(p: Perspective) -> Self {
+        Lens::Perspective(p)
+    }
+}
+
+impl From<Orthographic> for Lens {
+    fn from(o: Orthographic) -> Self {
+        Lens::Orthographic(o)
+    }
+}
+
+impl From<Matrix4<f32>> for Lens {
+    fn from(m: Matrix4<f32>) -> Self {
+        Lens::Matrix(m)
+    }
+}
+
 pub mod backend {
-    use camera::Camera;
+    use camera::{Camera, Viewport};
     use nalgebra::*;
 
     pub trait SetCamera {
         fn set_camera(&mut self, Matrix4<f32>, &Camera);
     }
+
+    // For backends that can draw several cameras into different parts of the same output. What
+    // is queued after the call is drawn with that camera inside that viewport.
+    pub trait SetViewport {
+        fn set_viewport(&mut self, Matrix4<f32>, &Camera, Viewport);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_backend::MockBackend;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    // Tangents of half the field of view, horizontally and vertically.
+    fn half_extents(lens: Lens) -> (f32, f32) {
+        match lens {
+            Lens::Perspective(p) => ((p.fov / 2.0).tan() * p.aspect, (p.fov / 2.0).tan()),
+            Lens::Orthographic(o) => ((o.right - o.left) / 2.0, (o.top - o.bottom) / 2.0),
+            Lens::Matrix(_) => panic!("no extents for a matrix lens"),
+        }
+    }
+
+    #[test]
+    fn fit_keeps_the_designed_view_visible() {
+        let designed = Perspective::new().fov(1.0).aspect(1.0);
+        let (width, height) = half_extents(designed.into());
+        let tracking = AspectTracking::new(designed, AspectPolicy::Fit);
+
+        let (w, h) = half_extents(tracking.lens_for(2.0));
+        assert!(close(h, height) && close(w, 2.0 * width));
+        let (w, h) = half_extents(tracking.lens_for(0.5));
+        assert!(close(w, width) && close(h, 2.0 * height));
+
+        let designed = Orthographic::extents(-2.0, 2.0, -1.0, 1.0);
+        let tracking = AspectTracking::new(designed, AspectPolicy::Fit);
+        assert_eq!(half_extents(tracking.lens_for(1.0)), (2.0, 2.0));
+    }
+
+    #[test]
+    fn fill_covers_the_viewport_with_the_designed_view() {
+        let designed = Perspective::new().fov(1.0).aspect(1.0);
+        let (width, height) = half_extents(designed.into());
+        let tracking = AspectTracking::new(designed, AspectPolicy::Fill);
+
+        let (w, h) = half_extents(tracking.lens_for(2.0));
+        assert!(close(w, width) && close(h, height / 2.0));
+        let (w, h) = half_extents(tracking.lens_for(0.5));
+        assert!(close(h, height) && close(w, width / 2.0));
+
+        let designed = Orthographic::extents(-2.0, 2.0, -1.0, 1.0);
+        let tracking = AspectTracking::new(designed, AspectPolicy::Fill);
+        assert_eq!(half_extents(tracking.lens_for(1.0)), (1.0, 1.0));
+    }
+
+    #[test]
+    fn stretch_and_matrix_lenses_are_left_alone() {
+        let designed = Lens::from(Perspective::new().aspect(1.0));
+        let stretched = AspectTracking::new(designed, AspectPolicy::Stretch);
+        assert_eq!(stretched.lens_for(3.0), designed);
+        let matrix = Lens::from(Matrix4::identity());
+        assert_eq!(AspectTracking::new(matrix, AspectPolicy::Fit).lens_for(3.0), matrix);
+    }
+
+    struct Screen {
+        cameras: Cameras,
+        sizes: Vec<ResizeEvent>,
+    }
+
+    impl Data for Screen {}
+
+    impl TrackAspect for Screen {
+        fn tracked_cameras<'a>(
+            &'a mut self,
+        ) -> Vec<(&'a mut Camera, &'a AspectTracking, Viewport)> {
+            self.cameras.tracked_cameras()
+        }
+        fn resized(&mut self, size: ResizeEvent) {
+            self.sizes.push(size);
+        }
+    }
+
+    #[test]
+    fn the_aspect_updater_follows_the_last_size_and_passes_every_size_on() {
+        let lens = Orthographic::extents(-2.0, 2.0, -1.0, 1.0);
+        let tracked = |name| {
+            ViewportCamera::new(name, Camera::new(lens))
+                .viewport(Viewport::new(0.0, 0.0, 0.5, 1.0))
+                .track_aspect(AspectPolicy::Fit)
+        };
+        let mut screen = Screen {
+            cameras: Cameras::new(),
+            sizes: Vec::new(),
+        };
+        screen.cameras.add(tracked("left"));
+        let mut backend = MockBackend::new();
+        let sizes = vec![ResizeEvent::new(400, 100), ResizeEvent::new(200, 100)];
+        backend.script_resize(sizes.clone());
+        let mut updater = AspectUpdater::new();
+
+        updater.update(&mut backend, &mut screen);
+        assert_eq!(screen.sizes, sizes);
+        let expected = AspectTracking::new(lens, AspectPolicy::Fit).lens_for(1.0);
+        assert_eq!(screen.cameras.get("left").unwrap().camera.lens, expected);
+
+        // Without new events the last size is still used, also for cameras added later
+        screen.cameras.add(tracked("right"));
+        updater.update(&mut backend, &mut screen);
+        assert_eq!(screen.cameras.get("right").unwrap().camera.lens, expected);
+        assert_eq!(screen.sizes.len(), 2);
+    }
+
+    fn lenses() -> Vec<Camera> {
+        let perspective = Perspective::new().fov(0.9).aspect(1.5).near(0.5).far(100.0);
+        vec![
+            Camera::new(perspective),
+            Camera::new(perspective.infinite()),
+            Camera::new(perspective.reversed_z(true)),
+            Camera::new(perspective.infinite().reversed_z(true)),
+            Camera::new(Orthographic::size(12.0, 1.5).near(1.0).far(100.0)),
+        ]
+    }
+
+    fn close_points(a: &Point3<f32>, b: &Point3<f32>) -> bool {
+        (a - b).norm() < 1e-3
+    }
+
+    #[test]
+    fn projected_points_unproject_back() {
+        let eye = Point3::new(3.0, 5.0, 20.0);
+        let view = Matrix4::look_at_rh(&eye, &Point3::origin(), &Vector3::y());
+        let forward = (Point3::origin() - eye).normalize();
+        let viewport = Vector2::new(300.0, 200.0);
+        let points = [
+            Point3::origin(),
+            Point3::new(2.0, -1.0, 3.0),
+            Point3::new(-4.0, 2.5, -6.0),
+            Point3::new(1.5, 3.0, 8.0),
+        ];
+        for camera in lenses() {
+            for point in points.iter() {
+                let pixel = camera.project(&view, point, viewport).unwrap();
+                assert!(pixel.x > 0.0 && pixel.x < viewport.x);
+                assert!(pixel.y > 0.0 && pixel.y < viewport.y);
+
+                let distance = (point - eye).dot(&forward);
+                let unprojected = camera.unproject(&view, &pixel, viewport, distance).unwrap();
+                assert!(close_points(&unprojected, point), "{:?} {:?}", camera, point);
+
+                let ray = camera.screen_ray(&view, &pixel, viewport).unwrap();
+                let along = (point - ray.origin).dot(&ray.direction);
+                assert!(along > 0.0);
+                assert!(close_points(&ray.at(along), point));
+
+                let ground = Plane::from_point_normal(*point, Vector3::y());
+                let hit = camera.unproject_onto_plane(&view, &pixel, viewport, &ground).unwrap();
+                assert!(close_points(&hit, point));
+            }
+        }
+    }
+
+    #[test]
+    fn points_behind_a_perspective_camera_are_not_projected() {
+        let eye = Point3::new(0.0, 0.0, 5.0);
+        let view = Matrix4::look_at_rh(&eye, &Point3::origin(), &Vector3::y());
+        let behind = Point3::new(0.0, 0.0, 10.0);
+        // All but the last, orthographic, one
+        for camera in lenses().iter().take(4) {
+            assert_eq!(camera.project(&view, &behind, Vector2::new(10.0, 10.0)), None);
+        }
+    }
 }
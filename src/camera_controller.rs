@@ -0,0 +1,586 @@
+use nalgebra::*;
+use std::f32;
+use std::f32::consts::FRAC_PI_2;
+
+use super::input::{Key, KeyboardEvent, MouseButton, MouseEvent};
+use super::time::Clock;
+
+// Turns input into the view matrix that `GetCamera::get_camera` returns. The data owning the
+// controller forwards it the events it receives through `OnMouse` and `OnKeyboard`, and calls
+// `advance` once per frame with its clock:
+//
+//     impl OnMouse for Data {
+//         fn handle(&mut self, event: MouseEvent) {
+//             self.controller.handle_mouse(&event);
+//         }
+//     }
+//     impl OnTick for Data {
+//         fn on_tick(&mut self, tick: Tick) {
+//             self.clock += tick;
+//             self.controller.advance(&self.clock);
+//         }
+//     }
+//     impl GetCamera for Data {
+//         fn get_camera(&self) -> (Matrix4<f32>, &Camera) {
+//             (self.controller.view(), &self.camera)
+//         }
+//     }
+pub trait CameraController {
+    fn handle_mouse(&mut self, _: &MouseEvent) {}
+    fn handle_keyboard(&mut self, _: &KeyboardEvent) {}
+    // Moves the camera towards where the input asked it to be.
+    fn advance(&mut self, clock: &Clock);
+    fn view(&self) -> Matrix4<f32>;
+}
+
+// Portion of the remaining distance to cover in `delta` seconds, so that about two thirds of the
+// way are covered every `smoothing` seconds. No smoothing jumps right away.
+fn approach(smoothing: f32, delta: f32) -> f32 {
+    if smoothing <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-delta / smoothing).exp()
+    }
+}
+
+fn clamp_pitch(pitch: f32) -> f32 {
+    let limit = FRAC_PI_2 - 0.01;
+    pitch.max(-limit).min(limit)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Buttons {
+    left: bool,
+    right: bool,
+    middle: bool,
+}
+
+impl Buttons {
+    fn handle(&mut self, event: &MouseEvent) {
+        let (button, pressed) = match *event {
+            MouseEvent::Pressed(ref button, _) => (button, true),
+            MouseEvent::Released(ref button, _) => (button, false),
+            _ => return,
+        };
+        match *button {
+            MouseButton::Left => self.left = pressed,
+            MouseButton::Right => self.right = pressed,
+            MouseButton::Middle => self.middle = pressed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Orbit {
+    target: Point3<f32>,
+    distance: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+// Rotates around a target point while dragging with the left button, pans the target with the
+// right or middle button and moves closer or further with the wheel.
+pub struct OrbitController {
+    goal: Orbit,
+    current: Orbit,
+    buttons: Buttons,
+    pub rotate_speed: f32,
+    pub pan_speed: f32,
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub smoothing: f32,
+}
+
+impl OrbitController {
+    pub fn new(target: Point3<f32>, distance: f32) -> Self {
+        let orbit = Orbit {
+            target,
+            distance,
+            yaw: 0.0,
+            pitch: 0.0,
+        };
+        OrbitController {
+            goal: orbit,
+            current: orbit,
+            buttons: Buttons::default(),
+            rotate_speed: 0.005,
+            pan_speed: 0.001,
+            zoom_speed: 0.1,
+            min_distance: 0.01,
+            max_distance: f32::INFINITY,
+            smoothing: 0.1,
+        }
+    }
+    // Angles in radians. Yaw turns around the vertical axis, a positive pitch looks from above.
+    pub fn angles(mut self, yaw: f32, pitch: f32) -> Self {
+        self.goal.yaw = yaw;
+        self.goal.pitch = clamp_pitch(pitch);
+        self.current = self.goal;
+        self
+    }
+    pub fn distance_limits(self, min_distance: f32, max_distance: f32) -> Self {
+        Self {
+            min_distance,
+            max_distance,
+            ..self
+        }
+    }
+    pub fn smoothing(self, smoothing: f32) -> Self {
+        Self { smoothing, ..self }
+    }
+
+    pub fn target(&self) -> Point3<f32> {
+        self.current.target
+    }
+    pub fn set_target(&mut self, target: Point3<f32>) {
+        self.goal.target = target;
+    }
+    pub fn eye(&self) -> Point3<f32> {
+        let o = &self.current;
+        let direction = Vector3::new(
+            o.pitch.cos() * o.yaw.sin(),
+            o.pitch.sin(),
+            o.pitch.cos() * o.yaw.cos(),
+        );
+        o.target + direction * o.distance
+    }
+}
+
+impl CameraController for OrbitController {
+    fn handle_mouse(&mut self, event: &MouseEvent) {
+        self.buttons.handle(event);
+        match *event {
+            MouseEvent::Movement(delta) if self.buttons.left => {
+                self.goal.yaw -= delta.x * self.rotate_speed;
+                self.goal.pitch = clamp_pitch(self.goal.pitch + delta.y * self.rotate_speed);
+            }
+            MouseEvent::Movement(delta) if self.buttons.right || self.buttons.middle => {
+                let rotation =
+                    UnitQuaternion::from_euler_angles(-self.goal.pitch, self.goal.yaw, 0.0);
+                let right = rotation * Vector3::x();
+                let up = rotation * Vector3::y();
+                let scale = self.pan_speed * self.goal.distance;
+                self.goal.target += (-right * delta.x + up * delta.y) * scale;
+            }
+            MouseEvent::Wheel(delta) => {
+                let distance = self.goal.distance * (1.0 - self.zoom_speed).powf(delta.y);
+                self.goal.distance = distance.max(self.min_distance).min(self.max_distance);
+            }
+            _ => {}
+        }
+    }
+
+    fn advance(&mut self, clock: &Clock) {
+        let t = approach(self.smoothing, clock.delta_as_sec());
+        let (c, g) = (&mut self.current, &self.goal);
+        c.target += (g.target - c.target) * t;
+        c.distance += (g.distance - c.distance) * t;
+        c.yaw += (g.yaw - c.yaw) * t;
+        c.pitch += (g.pitch - c.pitch) * t;
+    }
+
+    fn view(&self) -> Matrix4<f32> {
+        Matrix4::look_at_rh(&self.eye(), &self.current.target, &Vector3::y())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Movement {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+}
+
+// First person camera. W, A, S and D move along the ground plane of the view, E and Q move up and
+// down, and dragging with the right button looks around.
+pub struct FlyController {
+    position: Point3<f32>,
+    velocity: Vector3<f32>,
+    yaw: f32,
+    pitch: f32,
+    goal_yaw: f32,
+    goal_pitch: f32,
+    movement: Movement,
+    buttons: Buttons,
+    // Units per second.
+    pub speed: f32,
+    pub look_speed: f32,
+    pub smoothing: f32,
+}
+
+impl FlyController {
+    pub fn new(position: Point3<f32>) -> Self {
+        FlyController {
+            position,
+            velocity: Vector3::zeros(),
+            yaw: 0.0,
+            pitch: 0.0,
+            goal_yaw: 0.0,
+            goal_pitch: 0.0,
+            movement: Movement::default(),
+            buttons: Buttons::default(),
+            speed: 5.0,
+            look_speed: 0.005,
+            smoothing: 0.1,
+        }
+    }
+    // Angles in radians. With both at zero the camera looks towards negative z.
+    pub fn angles(self, yaw: f32, pitch: f32) -> Self {
+        let pitch = clamp_pitch(pitch);
+        Self {
+            yaw,
+            pitch,
+            goal_yaw: yaw,
+            goal_pitch: pitch,
+            ..self
+        }
+    }
+    pub fn speed(self, speed: f32) -> Self {
+        Self { speed, ..self }
+    }
+    pub fn smoothing(self, smoothing: f32) -> Self {
+        Self { smoothing, ..self }
+    }
+
+    pub fn position(&self) -> Point3<f32> {
+        self.position
+    }
+
+    fn rotation(&self) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.yaw)
+            * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), self.pitch)
+    }
+}
+
+impl CameraController for FlyController {
+    fn handle_mouse(&mut self, event: &MouseEvent) {
+        self.buttons.handle(event);
+        if let MouseEvent::Movement(delta) = *event {
+            if self.buttons.right {
+                self.goal_yaw -= delta.x * self.look_speed;
+                self.goal_pitch = clamp_pitch(self.goal_pitch - delta.y * self.look_speed);
+            }
+        }
+    }
+
+    fn handle_keyboard(&mut self, event: &KeyboardEvent) {
+        let (key, pressed) = match *event {
+            KeyboardEvent::Pressed(ref key, _) => (key, true),
+            KeyboardEvent::Released(ref key, _) => (key, false),
+        };
+        let m = &mut self.movement;
+        match *key {
+            Key::W => m.forward = pressed,
+            Key::S => m.back = pressed,
+            Key::A => m.left = pressed,
+            Key::D => m.right = pressed,
+            Key::E => m.up = pressed,
+            Key::Q => m.down = pressed,
+            _ => {}
+        }
+    }
+
+    fn advance(&mut self, clock: &Clock) {
+        let delta = clock.delta_as_sec();
+        let t = approach(self.smoothing, delta);
+        self.yaw += (self.goal_yaw - self.yaw) * t;
+        self.pitch += (self.goal_pitch - self.pitch) * t;
+
+        let axis = |positive: bool, negative: bool| match (positive, negative) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        };
+        let m = self.movement;
+        let heading = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.yaw);
+        let direction = heading
+            * Vector3::new(axis(m.right, m.left), 0.0, axis(m.back, m.forward))
+            + Vector3::y() * axis(m.up, m.down);
+        let goal_velocity = if direction.norm_squared() > 0.0 {
+            direction.normalize() * self.speed
+        } else {
+            Vector3::zeros()
+        };
+        self.velocity += (goal_velocity - self.velocity) * t;
+        self.position += self.velocity * delta;
+    }
+
+    fn view(&self) -> Matrix4<f32> {
+        self.rotation().inverse().to_homogeneous()
+            * Matrix4::new_translation(&-self.position.coords)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PanZoom {
+    center: Point2<f32>,
+    zoom: f32,
+}
+
+// For 2D scenes on the z = 0 plane seen through an orthographic camera. Dragging with the left
+// button moves the view and the wheel zooms in and out.
+pub struct PanZoomController {
+    goal: PanZoom,
+    current: PanZoom,
+    buttons: Buttons,
+    // World units per pixel of mouse movement at zoom 1.
+    pub pan_speed: f32,
+    pub zoom_speed: f32,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    // Distance from the plane to the camera, which has to fall between the near and far planes
+    // of the projection.
+    pub depth: f32,
+    pub smoothing: f32,
+}
+
+impl PanZoomController {
+    pub fn new(center: Point2<f32>, zoom: f32) -> Self {
+        let pan_zoom = PanZoom { center, zoom };
+        PanZoomController {
+            goal: pan_zoom,
+            current: pan_zoom,
+            buttons: Buttons::default(),
+            pan_speed: 0.01,
+            zoom_speed: 0.1,
+            min_zoom: 0.001,
+            max_zoom: 1000.0,
+            depth: 100.0,
+            smoothing: 0.1,
+        }
+    }
+    pub fn zoom_limits(self, min_zoom: f32, max_zoom: f32) -> Self {
+        Self {
+            min_zoom,
+            max_zoom,
+            ..self
+        }
+    }
+    pub fn smoothing(self, smoothing: f32) -> Self {
+        Self { smoothing, ..self }
+    }
+
+    pub fn center(&self) -> Point2<f32> {
+        self.current.center
+    }
+    pub fn zoom(&self) -> f32 {
+        self.current.zoom
+    }
+}
+
+impl CameraController for PanZoomController {
+    fn handle_mouse(&mut self, event: &MouseEvent) {
+        self.buttons.handle(event);
+        match *event {
+            MouseEvent::Movement(delta) if self.buttons.left => {
+                // Screen coordinates grow downwards
+                let pan = Vector2::new(-delta.x, delta.y) * (self.pan_speed / self.goal.zoom);
+                self.goal.center += pan;
+            }
+            MouseEvent::Wheel(delta) => {
+                let zoom = self.goal.zoom / (1.0 - self.zoom_speed).powf(delta.y);
+                self.goal.zoom = zoom.max(self.min_zoom).min(self.max_zoom);
+            }
+            _ => {}
+        }
+    }
+
+    fn advance(&mut self, clock: &Clock) {
+        let t = approach(self.smoothing, clock.delta_as_sec());
+        let (c, g) = (&mut self.current, &self.goal);
+        c.center += (g.center - c.center) * t;
+        // Zoom is interpolated in log space so zooming in and out feel the same
+        c.zoom *= (g.zoom / c.zoom).powf(t);
+    }
+
+    fn view(&self) -> Matrix4<f32> {
+        let c = &self.current;
+        Matrix4::new_nonuniform_scaling(&Vector3::new(c.zoom, c.zoom, 1.0))
+            * Matrix4::new_translation(&Vector3::new(-c.center.x, -c.center.y, -self.depth))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::{KeyModifiers, KeyboardUpdater, MouseUpdater, OnKeyboard, OnMouse};
+    use mock_backend::MockBackend;
+    use mursten::{Data, Updater};
+    use time::{FixedClockUpdater, OnTick, Tick};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    // Wired up as the comment on `CameraController` suggests
+    struct Viewer<C> {
+        controller: C,
+        clock: Clock,
+    }
+
+    impl<C> Data for Viewer<C> {}
+
+    impl<C: CameraController> OnMouse for Viewer<C> {
+        fn handle(&mut self, event: MouseEvent) {
+            self.controller.handle_mouse(&event);
+        }
+    }
+
+    impl<C: CameraController> OnKeyboard for Viewer<C> {
+        fn handle(&mut self, event: KeyboardEvent) {
+            self.controller.handle_keyboard(&event);
+        }
+    }
+
+    impl<C: CameraController> OnTick for Viewer<C> {
+        fn on_tick(&mut self, tick: Tick) {
+            self.clock += tick;
+            self.controller.advance(&self.clock);
+        }
+    }
+
+    fn viewer<C>(controller: C) -> Viewer<C> {
+        Viewer {
+            controller,
+            clock: Clock::new(),
+        }
+    }
+
+    // Runs a frame at 50 frames per second, handing over the next batch of scripted events.
+    fn frame<C: CameraController>(backend: &mut MockBackend, viewer: &mut Viewer<C>) {
+        MouseUpdater::new().update(backend, viewer);
+        KeyboardUpdater::new().update(backend, viewer);
+        FixedClockUpdater::new(50.0).update(backend, viewer);
+    }
+
+    fn drag(button: fn() -> MouseButton, x: f32, y: f32) -> Vec<MouseEvent> {
+        vec![
+            MouseEvent::Pressed(button(), Point2::origin()),
+            MouseEvent::Movement(Vector2::new(x, y)),
+            MouseEvent::Released(button(), Point2::new(x, y)),
+        ]
+    }
+
+    fn wheel(y: f32) -> Vec<MouseEvent> {
+        vec![MouseEvent::Wheel(Vector2::new(0.0, y))]
+    }
+
+    #[test]
+    fn the_orbit_controller_rotates_pans_and_zooms() {
+        let mut backend = MockBackend::new();
+        let mut v = viewer(OrbitController::new(Point3::origin(), 10.0).smoothing(0.0));
+        assert_eq!(v.controller.eye(), Point3::new(0.0, 0.0, 10.0));
+
+        // Dragging to the right swings the eye to the left around the target
+        backend.script_mouse(drag(|| MouseButton::Left, 100.0, 0.0));
+        frame(&mut backend, &mut v);
+        let eye = v.controller.eye();
+        assert!(close(eye.x, 10.0 * (-0.5f32).sin()));
+        assert!(close((eye - v.controller.target()).norm(), 10.0));
+
+        // Once released, moving the mouse does nothing
+        backend.script_mouse(vec![MouseEvent::Movement(Vector2::new(100.0, 100.0))]);
+        frame(&mut backend, &mut v);
+        assert_eq!(v.controller.eye(), eye);
+
+        backend.script_mouse(wheel(1.0));
+        frame(&mut backend, &mut v);
+        assert!(close((v.controller.eye() - v.controller.target()).norm(), 9.0));
+
+        // Panning moves the target and the eye together
+        let eye = v.controller.eye();
+        backend.script_mouse(drag(|| MouseButton::Right, 0.0, 100.0));
+        frame(&mut backend, &mut v);
+        let target = v.controller.target();
+        assert!(target.y > 0.0);
+        assert!(close(target.x, 0.0) && close(target.z, 0.0));
+        assert!(close((v.controller.eye() - eye).norm(), target.coords.norm()));
+    }
+
+    #[test]
+    fn the_orbit_controller_eases_towards_the_input() {
+        let mut backend = MockBackend::new();
+        let mut v = viewer(OrbitController::new(Point3::origin(), 10.0));
+        backend.script_mouse(wheel(-5.0));
+        let mut distances = vec![];
+        for _ in 0..100 {
+            frame(&mut backend, &mut v);
+            distances.push((v.controller.eye() - v.controller.target()).norm());
+        }
+        let goal = 10.0 * 0.9f32.powf(-5.0);
+        assert!(distances[0] > 10.0 && distances[0] < goal);
+        assert!(distances.windows(2).all(|d| d[0] <= d[1]));
+        assert!(close(distances[99], goal));
+    }
+
+    #[test]
+    fn the_fly_controller_moves_while_keys_are_held() {
+        let press = |key| vec![KeyboardEvent::Pressed(key, KeyModifiers {})];
+        let release = |key| vec![KeyboardEvent::Released(key, KeyModifiers {})];
+        let mut backend = MockBackend::new();
+        let mut v = viewer(FlyController::new(Point3::origin()).speed(2.0).smoothing(0.0));
+
+        // W moves forwards, towards negative z, for as long as it is held
+        backend.script_keyboard(press(Key::W));
+        for _ in 0..50 {
+            frame(&mut backend, &mut v);
+        }
+        assert!(close(v.controller.position().z, -2.0));
+        backend.script_keyboard(release(Key::W));
+        frame(&mut backend, &mut v);
+        let stopped = v.controller.position();
+        frame(&mut backend, &mut v);
+        assert_eq!(v.controller.position(), stopped);
+
+        // Dragging to the left turns left a quarter, after which D moves along the old forwards
+        let quarter_turn = -FRAC_PI_2 / v.controller.look_speed;
+        backend.script_mouse(drag(|| MouseButton::Right, quarter_turn, 0.0));
+        backend.script_keyboard(press(Key::D));
+        for _ in 0..50 {
+            frame(&mut backend, &mut v);
+        }
+        let moved = v.controller.position() - stopped;
+        assert!(close(moved.x, 0.0) && close(moved.y, 0.0));
+        assert!(close(moved.z, -2.0));
+
+        // E moves up, whatever the view
+        backend.script_keyboard(release(Key::D));
+        backend.script_keyboard(press(Key::E));
+        frame(&mut backend, &mut v);
+        let from = v.controller.position();
+        frame(&mut backend, &mut v);
+        let moved = v.controller.position() - from;
+        assert!(close(moved.x, 0.0) && close(moved.y, 0.04) && close(moved.z, 0.0));
+    }
+
+    #[test]
+    fn the_pan_zoom_controller_drags_the_view_and_zooms() {
+        let mut backend = MockBackend::new();
+        let mut v = viewer(PanZoomController::new(Point2::origin(), 1.0).smoothing(0.0));
+
+        // The content follows the mouse, so the center moves the other way
+        backend.script_mouse(drag(|| MouseButton::Left, 100.0, 100.0));
+        frame(&mut backend, &mut v);
+        assert!(close(v.controller.center().x, -1.0));
+        assert!(close(v.controller.center().y, 1.0));
+
+        // Zoomed in, the same drag moves the view less
+        backend.script_mouse(wheel(2.0));
+        frame(&mut backend, &mut v);
+        let zoom = 1.0 / 0.81;
+        assert!(close(v.controller.zoom(), zoom));
+        backend.script_mouse(drag(|| MouseButton::Left, 100.0, 0.0));
+        frame(&mut backend, &mut v);
+        assert!(close(v.controller.center().x, -1.0 - 1.0 / zoom));
+
+        // Other buttons do not pan
+        let center = v.controller.center();
+        backend.script_mouse(drag(|| MouseButton::Right, 100.0, 0.0));
+        frame(&mut backend, &mut v);
+        assert_eq!(v.controller.center(), center);
+    }
+}
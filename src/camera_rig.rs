@@ -0,0 +1,406 @@
+use mursten::{Backend, Data, Updater};
+use nalgebra::*;
+
+use super::camera::{backend, Camera, Lens, Perspective};
+use super::time::Clock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    // Seconds, compared against `Clock::time`.
+    pub time: f32,
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+    // Vertical field of view, in radians.
+    pub fov: f32,
+}
+
+impl Keyframe {
+    pub fn new(time: f32, position: Point3<f32>, target: Point3<f32>) -> Self {
+        Keyframe {
+            time,
+            position,
+            target,
+            fov: Perspective::default().fov,
+        }
+    }
+    pub fn fov(self, fov: f32) -> Self {
+        Self { fov, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    // Passes through every keyframe with a continuous velocity.
+    CatmullRom,
+}
+
+// Where the camera is at some point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub fov: f32,
+    // Extra rotation of the view, used for shaking.
+    pub shake: UnitQuaternion<f32>,
+}
+
+impl CameraPose {
+    // The view matrix, as returned by `GetCamera::get_camera`.
+    pub fn view(&self) -> Matrix4<f32> {
+        self.shake.inverse().to_homogeneous()
+            * Matrix4::look_at_rh(&self.position, &self.target, &self.up)
+    }
+
+    // Copy of `camera` with the field of view of the pose. Only perspective lenses change.
+    pub fn apply(&self, camera: &Camera) -> Camera {
+        let mut camera = camera.clone();
+        if let Lens::Perspective(ref mut p) = camera.lens {
+            p.fov = self.fov;
+        }
+        camera
+    }
+}
+
+// Keeps the apparent size of `subject` constant while the camera moves towards or away from it,
+// by widening or narrowing the field of view. `height` is the size the subject should span from
+// the bottom edge of the view to the top one, as the field of view of the pose is vertical.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DollyZoom {
+    pub subject: Point3<f32>,
+    pub height: f32,
+}
+
+// Procedural camera shake. Offsets follow smooth noise, so they wander instead of jittering.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shake {
+    // Maximum displacement of the camera, in world units.
+    pub translation: f32,
+    // Maximum rotation around each axis, in radians.
+    pub rotation: f32,
+    // Changes per second.
+    pub frequency: f32,
+    pub seed: u32,
+}
+
+impl Shake {
+    pub fn new(translation: f32, rotation: f32, frequency: f32) -> Self {
+        Shake {
+            translation,
+            rotation,
+            frequency,
+            seed: 0,
+        }
+    }
+    pub fn seed(self, seed: u32) -> Self {
+        Self { seed, ..self }
+    }
+
+    fn offsets(&self, time: f32) -> (Vector3<f32>, UnitQuaternion<f32>) {
+        let x = time * self.frequency;
+        let n = |channel: u32| noise(x, self.seed.wrapping_mul(6).wrapping_add(channel));
+        let translation = Vector3::new(n(0), n(1), n(2)) * self.translation;
+        let rotation = UnitQuaternion::from_euler_angles(
+            n(3) * self.rotation,
+            n(4) * self.rotation,
+            n(5) * self.rotation,
+        );
+        (translation, rotation)
+    }
+}
+
+// Gradient noise, between -1 and 1 and zero at every integer.
+fn noise(x: f32, seed: u32) -> f32 {
+    let gradient = |i: i32| {
+        let mut h = (i as u32).wrapping_mul(0x27d4_eb2d) ^ seed.wrapping_mul(0x9e37_79b9);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 13;
+        (h & 0xffff) as f32 / 32767.5 - 1.0
+    };
+    let i = x.floor();
+    let f = x - i;
+    let i = i as i32;
+    let fade = f * f * (3.0 - 2.0 * f);
+    let (a, b) = (gradient(i) * f, gradient(i + 1) * (f - 1.0));
+    // The largest value a one dimensional gradient noise can take is one half
+    2.0 * (a + (b - a) * fade)
+}
+
+// A scripted camera move. Keyframes give the position, target and field of view over time, and
+// the optional constraints are applied on top of them.
+//
+//     let rig = CameraRig::new()
+//         .keyframe(Keyframe::new(0.0, Point3::new(0.0, 2.0, 10.0), Point3::origin()))
+//         .keyframe(Keyframe::new(4.0, Point3::new(8.0, 3.0, 4.0), Point3::origin()))
+//         .shake(Shake::new(0.05, 0.01, 2.0));
+#[derive(Debug, Clone)]
+pub struct CameraRig {
+    keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+    pub looping: bool,
+    // Overrides the target of the keyframes.
+    pub look_at: Option<Point3<f32>>,
+    pub dolly_zoom: Option<DollyZoom>,
+    pub shake: Option<Shake>,
+    pub up: Vector3<f32>,
+}
+
+impl CameraRig {
+    pub fn new() -> Self {
+        CameraRig {
+            keyframes: Vec::new(),
+            interpolation: Interpolation::CatmullRom,
+            looping: false,
+            look_at: None,
+            dolly_zoom: None,
+            shake: None,
+            up: Vector3::y(),
+        }
+    }
+    // Keyframes are kept sorted by time.
+    pub fn keyframe(mut self, keyframe: Keyframe) -> Self {
+        self.add_keyframe(keyframe);
+        self
+    }
+    pub fn interpolation(self, interpolation: Interpolation) -> Self {
+        Self {
+            interpolation,
+            ..self
+        }
+    }
+    pub fn looping(self, looping: bool) -> Self {
+        Self { looping, ..self }
+    }
+    pub fn look_at(self, point: Point3<f32>) -> Self {
+        Self {
+            look_at: Some(point),
+            ..self
+        }
+    }
+    pub fn dolly_zoom(self, dolly_zoom: DollyZoom) -> Self {
+        Self {
+            dolly_zoom: Some(dolly_zoom),
+            ..self
+        }
+    }
+    pub fn shake(self, shake: Shake) -> Self {
+        Self {
+            shake: Some(shake),
+            ..self
+        }
+    }
+
+    pub fn add_keyframe(&mut self, keyframe: Keyframe) {
+        let index = self.keyframes
+            .iter()
+            .position(|k| k.time > keyframe.time)
+            .unwrap_or(self.keyframes.len());
+        self.keyframes.insert(index, keyframe);
+    }
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+    pub fn duration(&self) -> f32 {
+        match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    // Pose at `time` seconds. Before the first keyframe and after the last one the camera stays
+    // still, unless the rig loops.
+    pub fn pose(&self, time: f32) -> CameraPose {
+        let frame = self.interpolate(time);
+        let mut pose = CameraPose {
+            position: frame.position,
+            target: self.look_at.unwrap_or(frame.target),
+            up: self.up,
+            fov: frame.fov,
+            shake: UnitQuaternion::identity(),
+        };
+        if let Some(dolly) = self.dolly_zoom {
+            let distance = (dolly.subject - pose.position).norm();
+            if distance > 0.0 {
+                pose.fov = 2.0 * (dolly.height / (2.0 * distance)).atan();
+            }
+        }
+        if let Some(shake) = self.shake {
+            let (translation, rotation) = shake.offsets(time);
+            pose.position += translation;
+            pose.target += translation;
+            pose.shake = rotation;
+        }
+        pose
+    }
+
+    pub fn pose_at(&self, clock: &Clock) -> CameraPose {
+        self.pose(clock.time_in_sec())
+    }
+
+    fn interpolate(&self, time: f32) -> Keyframe {
+        let k = &self.keyframes;
+        if k.is_empty() {
+            return Keyframe::new(time, Point3::origin(), Point3::new(0.0, 0.0, -1.0));
+        }
+        let (first, last) = (k[0].time, k[k.len() - 1].time);
+        let time = if self.looping && last > first {
+            let offset = (time - first) % (last - first);
+            first + if offset < 0.0 { offset + last - first } else { offset }
+        } else {
+            time
+        };
+        if time <= first {
+            return k[0];
+        }
+        if time >= last {
+            return k[k.len() - 1];
+        }
+
+        let i = k.iter().rposition(|f| f.time <= time).unwrap_or(0);
+        let (a, b) = (&k[i], &k[i + 1]);
+        let span = b.time - a.time;
+        let t = if span > 0.0 { (time - a.time) / span } else { 0.0 };
+        match self.interpolation {
+            Interpolation::Linear => Keyframe {
+                time,
+                position: a.position + (b.position - a.position) * t,
+                target: a.target + (b.target - a.target) * t,
+                fov: a.fov + (b.fov - a.fov) * t,
+            },
+            Interpolation::CatmullRom => {
+                let frames = [&k[i.saturating_sub(1)], a, b, &k[(i + 2).min(k.len() - 1)]];
+                let position = catmull_rom(&frames, t, |f| f.position.coords);
+                let target = catmull_rom(&frames, t, |f| f.target.coords);
+                let fov = catmull_rom(&frames, t, |f| Vector3::new(f.fov, 0.0, 0.0));
+                Keyframe {
+                    time,
+                    position: Point3::from_coordinates(position),
+                    target: Point3::from_coordinates(target),
+                    fov: fov.x,
+                }
+            }
+        }
+    }
+}
+
+// Cubic through the two middle frames. Tangents are taken over time, so uneven keyframe spacing
+// does not make the speed jump at keyframes.
+fn catmull_rom<F>(frames: &[&Keyframe; 4], t: f32, value: F) -> Vector3<f32>
+where
+    F: Fn(&Keyframe) -> Vector3<f32>,
+{
+    let span = frames[2].time - frames[1].time;
+    let tangent = |from: &Keyframe, to: &Keyframe| {
+        let dt = to.time - from.time;
+        if dt > 0.0 {
+            (value(to) - value(from)) * (span / dt)
+        } else {
+            Vector3::zeros()
+        }
+    };
+    let (m1, m2) = (tangent(frames[0], frames[2]), tangent(frames[1], frames[3]));
+    let (p1, p2) = (value(frames[1]), value(frames[2]));
+    let (t2, t3) = (t * t, t * t * t);
+    p1 * (2.0 * t3 - 3.0 * t2 + 1.0) + m1 * (t3 - 2.0 * t2 + t) + p2 * (-2.0 * t3 + 3.0 * t2)
+        + m2 * (t3 - t2)
+}
+
+pub trait GetCameraRig {
+    // The rig, the camera whose lens it drives and the clock it is evaluated against.
+    fn get_camera_rig<'a>(&'a self) -> (&'a CameraRig, &'a Camera, &'a Clock);
+}
+
+// Like `camera::CameraUpdater`, but the camera follows the rig.
+pub struct CameraRigUpdater {}
+
+impl CameraRigUpdater {
+    pub fn new() -> Self {
+        CameraRigUpdater {}
+    }
+}
+
+impl<B, D> Updater<B, D> for CameraRigUpdater
+where
+    D: Data + GetCameraRig,
+    B: Backend<D> + backend::SetCamera,
+{
+    fn update(&mut self, backend: &mut B, data: &mut D) {
+        let (rig, camera, clock) = data.get_camera_rig();
+        let pose = rig.pose_at(clock);
+        backend.set_camera(pose.view(), &pose.apply(camera));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &Point3<f32>, b: &Point3<f32>) -> bool {
+        (a - b).norm() < 1e-4
+    }
+
+    fn rig(interpolation: Interpolation) -> CameraRig {
+        let at = |time, x, fov| {
+            Keyframe::new(time, Point3::new(x, 2.0, 10.0), Point3::new(x, 0.0, 0.0)).fov(fov)
+        };
+        CameraRig::new()
+            .keyframe(at(1.0, 0.0, 0.8))
+            .keyframe(at(3.0, 4.0, 1.0))
+            .keyframe(at(4.0, 2.0, 0.6))
+            .keyframe(at(7.0, -3.0, 1.2))
+            .interpolation(interpolation)
+    }
+
+    #[test]
+    fn poses_pass_through_the_keyframes() {
+        for &interpolation in &[Interpolation::Linear, Interpolation::CatmullRom] {
+            let rig = rig(interpolation);
+            for keyframe in rig.keyframes() {
+                let pose = rig.pose(keyframe.time);
+                assert!(close(&pose.position, &keyframe.position));
+                assert!(close(&pose.target, &keyframe.target));
+                assert!((pose.fov - keyframe.fov).abs() < 1e-5);
+            }
+        }
+        let linear = rig(Interpolation::Linear);
+        assert!(close(&linear.pose(2.0).position, &Point3::new(2.0, 2.0, 10.0)));
+        // Outside of the keyframes the camera stays at the ends
+        assert_eq!(linear.pose(-5.0).position, linear.keyframes()[0].position);
+        assert_eq!(linear.pose(50.0).position, linear.keyframes()[3].position);
+    }
+
+    #[test]
+    fn looping_rigs_wrap_around_in_both_directions() {
+        for &interpolation in &[Interpolation::Linear, Interpolation::CatmullRom] {
+            let rig = rig(interpolation).looping(true);
+            assert_eq!(rig.duration(), 6.0);
+            for &time in &[1.0, 2.5, 3.0, 5.5, 6.9] {
+                let pose = rig.pose(time).position;
+                for &loops in &[-3.0, -1.0, 1.0, 2.0] {
+                    let wrapped = rig.pose(time + loops * 6.0).position;
+                    assert!(close(&wrapped, &pose), "{} {}", time, loops);
+                }
+            }
+            // The end of one loop is the start of the next
+            assert!(close(&rig.pose(7.0).position, &rig.pose(1.0).position));
+            assert!(close(&rig.pose(0.0).position, &rig.pose(6.0).position));
+        }
+    }
+
+    #[test]
+    fn dolly_zoom_keeps_the_subject_height() {
+        let dolly = DollyZoom {
+            subject: Point3::origin(),
+            height: 3.0,
+        };
+        let rig = rig(Interpolation::CatmullRom).dolly_zoom(dolly);
+        for i in 0..20 {
+            let pose = rig.pose(1.0 + i as f32 * 0.3);
+            let distance = (dolly.subject - pose.position).norm();
+            let height = 2.0 * distance * (pose.fov / 2.0).tan();
+            assert!((height - dolly.height).abs() < 1e-4);
+        }
+    }
+}
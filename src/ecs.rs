@@ -0,0 +1,496 @@
+use alga::linear::Transformation;
+use nalgebra::*;
+use std::any::{Any, TypeId};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::vec;
+
+use super::camera::{Camera, GetCamera};
+use super::geometry::Mesh;
+use super::light::{GetLights, Light};
+use super::material::Material;
+use super::mesh_renderer::{GetMeshes, IntoMesh};
+use super::scene::Transform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    storages: HashMap<TypeId, Box<AnyStorage>>,
+    // Given by `get_camera` when no entity has a `Camera`, placed at the origin.
+    pub default_camera: Camera,
+}
+
+pub struct Storage<T> {
+    components: Vec<Option<T>>,
+}
+
+impl<T> Storage<T> {
+    fn new() -> Self {
+        Storage {
+            components: Vec::new(),
+        }
+    }
+    fn get(&self, index: usize) -> Option<&T> {
+        self.components.get(index).and_then(|c| c.as_ref())
+    }
+    fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.components.get_mut(index).and_then(|c| c.as_mut())
+    }
+    fn insert(&mut self, index: usize, component: T) -> Option<T> {
+        while self.components.len() <= index {
+            self.components.push(None);
+        }
+        ::std::mem::replace(&mut self.components[index], Some(component))
+    }
+    fn take(&mut self, index: usize) -> Option<T> {
+        self.components.get_mut(index).and_then(|c| c.take())
+    }
+    pub fn len(&self) -> usize {
+        self.components.iter().filter(|c| c.is_some()).count()
+    }
+}
+
+trait AnyStorage {
+    fn clear(&mut self, index: usize);
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl<T: 'static> AnyStorage for Storage<T> {
+    fn clear(&mut self, index: usize) {
+        self.take(index);
+    }
+    fn as_any(&self) -> &Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+impl World {
+    pub fn new() -> Self {
+        World {
+            generations: Vec::new(),
+            alive: Vec::new(),
+            free: Vec::new(),
+            storages: HashMap::new(),
+            default_camera: Camera::perspective(),
+        }
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                self.alive[index as usize] = true;
+                Entity {
+                    index,
+                    generation: self.generations[index as usize],
+                }
+            }
+            None => {
+                let index = self.generations.len() as u32;
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity {
+                    index,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn create(&mut self) -> EntityBuilder {
+        let entity = self.spawn();
+        EntityBuilder {
+            world: self,
+            entity,
+        }
+    }
+
+    // Removes the entity with all its components. The handle, and any copy of it, becomes stale.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        let index = entity.index as usize;
+        for storage in self.storages.values_mut() {
+            storage.clear(index);
+        }
+        self.alive[index] = false;
+        self.generations[index] += 1;
+        self.free.push(entity.index);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        index < self.alive.len() && self.alive[index]
+            && self.generations[index] == entity.generation
+    }
+
+    pub fn entities(&self) -> vec::IntoIter<Entity> {
+        let entities: Vec<Entity> = (0..self.alive.len())
+            .filter(|&i| self.alive[i])
+            .map(|i| Entity {
+                index: i as u32,
+                generation: self.generations[i],
+            })
+            .collect();
+        entities.into_iter()
+    }
+
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Storage::<T>::new()))
+            .as_any_mut()
+            .downcast_mut::<Storage<T>>()
+            .unwrap()
+            .insert(entity.index as usize, component)
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.storage_mut::<T>()?.take(entity.index as usize)
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.storage::<T>()?.get(entity.index as usize)
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.storage_mut::<T>()?.get_mut(entity.index as usize)
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    pub fn storage<T: 'static>(&self) -> Option<&Storage<T>> {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .and_then(|s| s.as_any().downcast_ref::<Storage<T>>())
+    }
+
+    fn storage_mut<T: 'static>(&mut self) -> Option<&mut Storage<T>> {
+        self.storages
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|s| s.as_any_mut().downcast_mut::<Storage<T>>())
+    }
+
+    // Entities having every component of the query, e.g. `world.query::<(&Transform, &Light)>()`.
+    pub fn query<'a, Q>(&'a self) -> vec::IntoIter<(Entity, Q::Item)>
+    where
+        Q: Query<'a>,
+    {
+        let result: Vec<(Entity, Q::Item)> = self.entities()
+            .filter_map(|e| Q::fetch(self, e).map(|item| (e, item)))
+            .collect();
+        result.into_iter()
+    }
+
+    pub fn query_mut<T: 'static>(&mut self) -> vec::IntoIter<(Entity, &mut T)> {
+        let generations = &self.generations;
+        let alive = &self.alive;
+        let result: Vec<(Entity, &mut T)> = match self.storages.get_mut(&TypeId::of::<T>()) {
+            Some(storage) => storage
+                .as_any_mut()
+                .downcast_mut::<Storage<T>>()
+                .unwrap()
+                .components
+                .iter_mut()
+                .enumerate()
+                .filter(|&(i, _)| alive[i])
+                .filter_map(|(i, c)| {
+                    c.as_mut().map(|c| {
+                        let entity = Entity {
+                            index: i as u32,
+                            generation: generations[i],
+                        };
+                        (entity, c)
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        result.into_iter()
+    }
+}
+
+pub struct EntityBuilder<'a> {
+    world: &'a mut World,
+    entity: Entity,
+}
+
+impl<'a> EntityBuilder<'a> {
+    pub fn with<T: 'static>(self, component: T) -> Self {
+        self.world.insert(self.entity, component);
+        self
+    }
+    pub fn build(self) -> Entity {
+        self.entity
+    }
+}
+
+pub trait Query<'a> {
+    type Item;
+    fn fetch(world: &'a World, entity: Entity) -> Option<Self::Item>;
+}
+
+impl<'a, T: 'static> Query<'a> for &'a T {
+    type Item = &'a T;
+    fn fetch(world: &'a World, entity: Entity) -> Option<Self::Item> {
+        world.get::<T>(entity)
+    }
+}
+
+macro_rules! impl_query_for_tuple {
+    ($($q:ident),*) => {
+        impl<'a, $($q),*> Query<'a> for ($($q,)*)
+        where
+            $($q: Query<'a>),*
+        {
+            type Item = ($($q::Item,)*);
+            fn fetch(world: &'a World, entity: Entity) -> Option<Self::Item> {
+                Some(($($q::fetch(world, entity)?,)*))
+            }
+        }
+    };
+}
+
+impl_query_for_tuple!(A);
+impl_query_for_tuple!(A, B);
+impl_query_for_tuple!(A, B, C);
+impl_query_for_tuple!(A, B, C, D);
+impl_query_for_tuple!(A, B, C, D, E);
+
+/// Built-in components
+/// -------------------
+
+// Entities with a `Renderable` are drawn with their `Transform`, if they have one, applied on
+// top of the mesh's own transform.
+pub struct Renderable {
+    mesh: Box<IntoMesh>,
+    world: Cell<Matrix4<f32>>,
+}
+
+impl Renderable {
+    pub fn new<M>(mesh: M) -> Self
+    where
+        M: IntoMesh + 'static,
+    {
+        Renderable {
+            mesh: Box::new(mesh),
+            world: Cell::new(Matrix4::identity()),
+        }
+    }
+}
+
+impl IntoMesh for Renderable {
+    fn transform(&self) -> Matrix4<f32> {
+        self.world.get() * self.mesh.transform()
+    }
+    fn mesh(&self) -> Mesh {
+        self.mesh.mesh()
+    }
+    fn material(&self) -> Material {
+        self.mesh.material()
+    }
+    fn batch_key(&self) -> u64 {
+        self.mesh.batch_key()
+    }
+}
+
+// Marks which of the entities with a `Camera` is used for rendering. Without it the first camera
+// found is used.
+pub struct ActiveCamera;
+
+fn world_matrix(world: &World, entity: Entity) -> Matrix4<f32> {
+    world
+        .get::<Transform>(entity)
+        .map_or(Matrix4::identity(), |t| t.to_matrix())
+}
+
+impl World {
+    fn active_camera(&self) -> Option<(Entity, &Camera)> {
+        self.query::<(&Camera, &ActiveCamera)>()
+            .map(|(e, (c, _))| (e, c))
+            .next()
+            .or_else(|| self.query::<&Camera>().next())
+    }
+}
+
+// The camera transform is the view matrix, the inverse of the camera entity placement.
+//
+// Data holding a `World` can be driven by `camera::CameraUpdater`, `light::LightUpdater` and
+// `mesh_renderer::MeshRenderer` by implementing `GetCamera`, `GetLights` and `GetMeshes` with
+// calls to the world. Each one can also be taken from elsewhere instead.
+impl GetCamera for World {
+    fn get_camera<'a>(&'a self) -> (Matrix4<f32>, &'a Camera) {
+        self.find_camera()
+            .unwrap_or((Matrix4::identity(), &self.default_camera))
+    }
+    fn find_camera<'a>(&'a self) -> Option<(Matrix4<f32>, &'a Camera)> {
+        self.active_camera().map(|(entity, camera)| {
+            let view = world_matrix(self, entity)
+                .try_inverse()
+                .unwrap_or(Matrix4::identity());
+            (view, camera)
+        })
+    }
+}
+
+// Lights are placed by the transform of their entity.
+impl GetLights for World {
+    fn get_lights(&self) -> Vec<Light> {
+        self.query::<&Light>()
+            .map(|(entity, light)| light.transform(&world_matrix(self, entity)))
+            .collect()
+    }
+}
+
+impl GetMeshes for World {
+    fn mesh_iter<'a>(&'a self) -> vec::IntoIter<&IntoMesh> {
+        let meshes: Vec<&IntoMesh> = self.query::<&Renderable>()
+            .map(|(e, r)| {
+                r.world.set(world_matrix(self, e));
+                r as &IntoMesh
+            })
+            .collect();
+        meshes.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::CameraUpdater;
+    use geometry::Triangle;
+    use light::LightUpdater;
+    use mesh_renderer::MeshRenderer;
+    use mock_backend::MockBackend;
+    use mursten::{Data, Renderer, Updater};
+
+    struct Game {
+        world: World,
+    }
+
+    impl Data for Game {}
+
+    impl GetCamera for Game {
+        fn get_camera<'a>(&'a self) -> (Matrix4<f32>, &'a Camera) {
+            self.world.get_camera()
+        }
+        fn find_camera<'a>(&'a self) -> Option<(Matrix4<f32>, &'a Camera)> {
+            self.world.find_camera()
+        }
+    }
+
+    impl GetLights for Game {
+        fn get_lights(&self) -> Vec<Light> {
+            self.world.get_lights()
+        }
+    }
+
+    impl GetMeshes for Game {
+        fn mesh_iter<'a>(&'a self) -> vec::IntoIter<&IntoMesh> {
+            self.world.mesh_iter()
+        }
+    }
+
+    fn placed(x: f32) -> Transform {
+        Transform::identity().translation(Vector3::new(x, 0.0, 0.0))
+    }
+
+    #[test]
+    fn a_world_without_camera_sets_none() {
+        let mut game = Game {
+            world: World::new(),
+        };
+        let mut backend = MockBackend::new();
+        CameraUpdater::new().update(&mut backend, &mut game);
+        MeshRenderer::with_frustum_culling()
+            .depth_sorted()
+            .render(&mut backend, &game);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[test]
+    fn get_camera_falls_back_to_the_default_camera() {
+        let mut world = World::new();
+        world.default_camera = Camera::orthographic();
+        let (view, camera) = world.get_camera();
+        assert_eq!(view, Matrix4::identity());
+        assert_eq!(*camera, Camera::orthographic());
+    }
+
+    #[test]
+    fn the_world_drives_the_existing_updaters_and_renderer() {
+        let mut game = Game {
+            world: World::new(),
+        };
+        game.world
+            .create()
+            .with(placed(0.0).translation(Vector3::new(0.0, 0.0, 5.0)))
+            .with(Camera::perspective())
+            .build();
+        game.world
+            .create()
+            .with(placed(2.0))
+            .with(Light::point(Point3::origin(), Vector3::new(1.0, 1.0, 1.0), 1.0))
+            .build();
+        let mesh = Mesh {
+            triangles: vec![Triangle::default()],
+        };
+        game.world
+            .create()
+            .with(placed(1.0))
+            .with(Renderable::new(mesh.clone()))
+            .build();
+        // Far behind the camera, so culled
+        game.world
+            .create()
+            .with(placed(0.0).translation(Vector3::new(0.0, 0.0, 50.0)))
+            .with(Renderable::new(mesh))
+            .build();
+
+        let mut backend = MockBackend::new();
+        CameraUpdater::new().update(&mut backend, &mut game);
+        LightUpdater::new().update(&mut backend, &mut game);
+        let mut renderer = MeshRenderer::with_frustum_culling();
+        renderer.render(&mut backend, &game);
+
+        let cameras = backend.cameras();
+        assert_eq!(cameras.len(), 1);
+        assert_eq!(
+            cameras[0].0,
+            Matrix4::new_translation(&Vector3::new(0.0, 0.0, -5.0))
+        );
+        let lights = backend.lights();
+        assert_eq!(lights.len(), 1);
+        assert_eq!(lights[0][0].position(), Some(Point3::new(2.0, 0.0, 0.0)));
+        let meshes = backend.rendered_meshes();
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(*meshes[0].0, placed(1.0).to_matrix());
+        assert_eq!(renderer.stats().culled, 1);
+    }
+}
@@ -2,6 +2,8 @@ use nalgebra::*;
 use alga::linear::Transformation;
 use std::vec;
 
+use super::bounds::Aabb;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     pub position: Point3<f32>,
@@ -80,6 +82,12 @@ impl Triangle {
             ..self
         }
     }
+    pub fn bounds(&self) -> Aabb {
+        Aabb::empty()
+            .grow(self.v1.position)
+            .grow(self.v2.position)
+            .grow(self.v3.position)
+    }
 }
 
 impl IntoIterator for Triangle {
@@ -119,5 +127,10 @@ impl Mesh {
             ..self
         }
     }
+    pub fn bounds(&self) -> Aabb {
+        self.triangles
+            .iter()
+            .fold(Aabb::empty(), |b, t| b.union(t.bounds()))
+    }
 }
 
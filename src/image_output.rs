@@ -0,0 +1,368 @@
+use mursten::{Backend, Data, Renderer};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::software_renderer::Framebuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("png") => Some(ImageFormat::Png),
+            Some("ppm") => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+}
+
+// Picks the format from the file extension.
+pub fn save<P: AsRef<Path>>(path: P, framebuffer: &Framebuffer) -> io::Result<()> {
+    let format = ImageFormat::from_path(&path).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Unknown image file extension")
+    })?;
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        ImageFormat::Png => write_png(&mut file, framebuffer),
+        ImageFormat::Ppm => write_ppm(&mut file, framebuffer),
+    }
+}
+
+// Binary PPM. The format has no alpha channel, so it is dropped.
+pub fn write_ppm<W: Write>(out: &mut W, framebuffer: &Framebuffer) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", framebuffer.width(), framebuffer.height())?;
+    let rgb: Vec<u8> = framebuffer
+        .as_bytes()
+        .chunks(4)
+        .flat_map(|p| p[..3].iter().cloned())
+        .collect();
+    out.write_all(&rgb)?;
+    out.flush()
+}
+
+// Reads binary PPM files like the ones written by `write_ppm`, for comparing against reference
+// images. Pixels are fully opaque.
+pub fn read_ppm<R: Read>(input: R) -> io::Result<Framebuffer> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut input = BufReader::new(input);
+    let mut header = Vec::new();
+    while header.len() < 4 {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(invalid("Truncated PPM header"));
+        }
+        let line = line.split('#').next().unwrap_or("");
+        header.extend(line.split_whitespace().map(|w| w.to_string()));
+    }
+    if header[0] != "P6" {
+        return Err(invalid("Only binary PPM (P6) is supported"));
+    }
+    let number = |s: &str| s.parse::<usize>().map_err(|_| invalid("Invalid PPM header"));
+    let (width, height, max) = (number(&header[1])?, number(&header[2])?, number(&header[3])?);
+    if max != 255 {
+        return Err(invalid("Only 8 bit PPM is supported"));
+    }
+    let mut rgb = vec![0; width * height * 3];
+    input.read_exact(&mut rgb)?;
+    let mut framebuffer = Framebuffer::new(width, height);
+    for (i, p) in rgb.chunks(3).enumerate() {
+        framebuffer.set(i % width, i / width, [p[0], p[1], p[2], 255]);
+    }
+    Ok(framebuffer)
+}
+
+// RGBA PNG. The image data is stored without compression, which keeps the encoder tiny at the
+// cost of file size.
+pub fn write_png<W: Write>(out: &mut W, framebuffer: &Framebuffer) -> io::Result<()> {
+    out.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&be32(framebuffer.width() as u32));
+    header.extend_from_slice(&be32(framebuffer.height() as u32));
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    let row_length = framebuffer.width() * 4;
+    let mut raw = Vec::with_capacity((row_length + 1) * framebuffer.height());
+    for row in framebuffer.as_bytes().chunks(row_length.max(1)) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(out, b"IEND", &[])?;
+    out.flush()
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&be32(data.len() as u32))?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()));
+    out.write_all(&be32(crc))
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+    if blocks.is_empty() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        let last = if i + 1 == blocks.len() { 1 } else { 0 };
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&[(len & 0xff) as u8, (len >> 8) as u8]);
+        out.extend_from_slice(&[(!len & 0xff) as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&be32(adler32(data)));
+    out
+}
+
+fn be32(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn crc32<'a, I: Iterator<Item = &'a u8>>(bytes: I) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDifference {
+    pub differing_pixels: usize,
+    pub max_difference: u8,
+}
+
+// Per channel comparison, for golden image tests. Channels that differ by no more than
+// `tolerance` count as equal. Returns `None` when the sizes differ.
+pub fn compare(a: &Framebuffer, b: &Framebuffer, tolerance: u8) -> Option<ImageDifference> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return None;
+    }
+    let mut difference = ImageDifference {
+        differing_pixels: 0,
+        max_difference: 0,
+    };
+    for (pa, pb) in a.as_bytes().chunks(4).zip(b.as_bytes().chunks(4)) {
+        let d = pa.iter()
+            .zip(pb.iter())
+            .map(|(&x, &y)| if x > y { x - y } else { y - x })
+            .max()
+            .unwrap_or(0);
+        if d > tolerance {
+            difference.differing_pixels += 1;
+        }
+        difference.max_difference = difference.max_difference.max(d);
+    }
+    Some(difference)
+}
+
+// Writes numbered frames, `frame_00001.png`, `frame_00002.png`... into a directory.
+pub struct ImageSequence {
+    directory: PathBuf,
+    prefix: String,
+    format: ImageFormat,
+    next_frame: usize,
+}
+
+impl ImageSequence {
+    pub fn new<P: AsRef<Path>>(directory: P, format: ImageFormat) -> Self {
+        ImageSequence {
+            directory: directory.as_ref().to_path_buf(),
+            prefix: "frame".to_string(),
+            format,
+            next_frame: 1,
+        }
+    }
+    pub fn prefix(self, prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            ..self
+        }
+    }
+    pub fn next_frame(&self) -> usize {
+        self.next_frame
+    }
+    pub fn path_of(&self, frame: usize) -> PathBuf {
+        self.directory.join(format!(
+            "{}_{:05}.{}",
+            self.prefix,
+            frame,
+            self.format.extension()
+        ))
+    }
+    pub fn write(&mut self, framebuffer: &Framebuffer) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self.path_of(self.next_frame);
+        save(&path, framebuffer)?;
+        self.next_frame += 1;
+        Ok(path)
+    }
+}
+
+// Saves every rendered frame of the backend into an image sequence. Add it after the renderers
+// that draw the frame. For offline renders pair it with `time::FixedClockUpdater`, so each
+// image advances the data exactly one frame at the chosen frame rate, however long rendering
+// takes. A frame that cannot be written also stops the backend, see `error`.
+pub struct FrameRecorder {
+    sequence: ImageSequence,
+    max_frames: Option<usize>,
+    error: Option<io::Error>,
+}
+
+impl FrameRecorder {
+    pub fn new(sequence: ImageSequence) -> Self {
+        FrameRecorder {
+            sequence,
+            max_frames: None,
+            error: None,
+        }
+    }
+    // Stops the backend after writing this many frames.
+    pub fn max_frames(self, max_frames: usize) -> Self {
+        Self {
+            max_frames: Some(max_frames),
+            ..self
+        }
+    }
+    pub fn frames_written(&self) -> usize {
+        self.sequence.next_frame() - 1
+    }
+    // Why the recording stopped early, if it did.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<B, D> Renderer<B, D> for FrameRecorder
+where
+    D: Data,
+    B: Backend<D> + backend::FrameSource,
+{
+    fn render(&mut self, backend: &mut B, _: &D) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.sequence.write(backend.frame()) {
+            self.error = Some(err);
+            backend.quit();
+            return;
+        }
+        if let Some(max) = self.max_frames {
+            if self.frames_written() >= max {
+                backend.quit();
+            }
+        }
+    }
+}
+
+pub mod backend {
+    use software_renderer::Framebuffer;
+
+    pub trait FrameSource {
+        fn frame(&mut self) -> &Framebuffer;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::backend::SetCamera;
+    use camera::{Camera, Orthographic};
+    use geometry::{Mesh, Triangle, Vertex};
+    use material::Material;
+    use mesh_renderer::backend::RenderMaterialMesh;
+    use mock_backend::MockBackend;
+    use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+    use software_renderer::SoftwareRenderer;
+    use std::env;
+
+    struct Empty;
+
+    impl Data for Empty {}
+
+    #[test]
+    fn failed_writes_stop_the_recording() {
+        // A file where the directory should be
+        let blocked = env::temp_dir().join("mursten_blocks_frame_recorder_blocked");
+        File::create(&blocked).unwrap();
+        let mut recorder =
+            FrameRecorder::new(ImageSequence::new(&blocked, ImageFormat::Ppm)).max_frames(3);
+        let mut backend = MockBackend::new();
+        recorder.render(&mut backend, &Empty);
+        recorder.render(&mut backend, &Empty);
+        fs::remove_file(&blocked).unwrap();
+
+        assert!(recorder.error().is_some());
+        assert_eq!(recorder.frames_written(), 0);
+        assert_eq!(backend.quit_count(), 1);
+    }
+
+    #[test]
+    fn records_the_frames_the_backend_draws() {
+        let directory = env::temp_dir().join("mursten_blocks_frame_recorder_frames");
+        let _ = fs::remove_dir_all(&directory);
+        let mut recorder = FrameRecorder::new(ImageSequence::new(&directory, ImageFormat::Ppm));
+        let mut renderer = SoftwareRenderer::new(40, 40);
+        let lens = Orthographic::extents(-20.0, 20.0, -20.0, 20.0).near(0.1).far(100.0);
+        renderer.set_camera(Matrix4::identity(), &Camera::new(lens));
+        // Only covers the left half of the screen, then the right half
+        let red = |x, y| {
+            Vertex::at(Point3::new(x, y, -5.0)).color(Vector4::new(1.0, 0.0, 0.0, 1.0))
+        };
+        let left = Mesh {
+            triangles: vec![Triangle::new(red(-18.0, -10.0), red(-2.0, -10.0), red(-10.0, 10.0))],
+        };
+        let unlit = Material::new().unlit(true);
+        renderer.queue_render_material(Matrix4::identity(), left.clone(), unlit.clone());
+        recorder.render(&mut renderer, &Empty);
+        let right = Matrix4::new_translation(&Vector3::new(20.0, 0.0, 0.0));
+        renderer.queue_render_material(right, left, unlit);
+        recorder.render(&mut renderer, &Empty);
+
+        let read = |frame| {
+            let path = recorder.sequence.path_of(frame);
+            read_ppm(File::open(path).unwrap()).unwrap()
+        };
+        let (first, second) = (read(1), read(2));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(recorder.frames_written(), 2);
+        assert_eq!(first.get(10, 20), [255, 0, 0, 255]);
+        assert_eq!(first.get(30, 20), [0, 0, 0, 255]);
+        assert_eq!(second.get(10, 20), [0, 0, 0, 255]);
+        assert_eq!(second.get(30, 20), [255, 0, 0, 255]);
+    }
+}
@@ -41,6 +41,31 @@ pub enum MouseButton {
     Middle,
 }
 
+// The output of the backend changed size. Backends also send one with the initial size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResizeEvent {
+    pub width: usize,
+    pub height: usize,
+    // Width over height of a single pixel, for outputs whose pixels are not square.
+    pub pixel_aspect: f32,
+}
+
+impl ResizeEvent {
+    pub fn new(width: usize, height: usize) -> Self {
+        ResizeEvent {
+            width,
+            height,
+            pixel_aspect: 1.0,
+        }
+    }
+    pub fn pixel_aspect(self, pixel_aspect: f32) -> Self {
+        Self {
+            pixel_aspect,
+            ..self
+        }
+    }
+}
+
 pub struct KeyboardUpdater {}
 
 impl KeyboardUpdater {
@@ -98,5 +123,9 @@ pub mod backend {
     pub trait MouseEventSource {
         fn drain_events(&mut self) -> Vec<super::MouseEvent>;
     }
+
+    pub trait ResizeEventSource {
+        fn drain_events(&mut self) -> Vec<super::ResizeEvent>;
+    }
 }
 
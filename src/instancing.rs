@@ -0,0 +1,143 @@
+use mursten::{Backend, Data, Renderer};
+use nalgebra::*;
+use std::vec;
+
+use super::geometry::{Mesh, Triangle, Vertex};
+use super::mesh_renderer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instance {
+    pub transform: Matrix4<f32>,
+    // Multiplies the vertex colors of the mesh.
+    pub tint: Vector4<f32>,
+}
+
+impl Instance {
+    pub fn new(transform: Matrix4<f32>) -> Self {
+        Instance {
+            transform,
+            tint: Vector4::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+    pub fn tint(self, tint: Vector4<f32>) -> Self {
+        Self { tint, ..self }
+    }
+}
+
+// Same mesh drawn once per instance.
+#[derive(Debug, Clone)]
+pub struct InstancedMesh {
+    pub mesh: Mesh,
+    pub instances: Vec<Instance>,
+}
+
+impl InstancedMesh {
+    pub fn new(mesh: Mesh) -> Self {
+        InstancedMesh {
+            mesh,
+            instances: Vec::new(),
+        }
+    }
+    pub fn instance(mut self, instance: Instance) -> Self {
+        self.instances.push(instance);
+        self
+    }
+}
+
+pub trait GetInstances {
+    fn instance_iter<'a>(&'a self) -> vec::IntoIter<&IntoInstances>;
+}
+
+pub trait IntoInstances {
+    fn mesh(&self) -> Mesh;
+    fn instances(&self) -> Vec<Instance>;
+}
+
+impl IntoInstances for InstancedMesh {
+    fn mesh(&self) -> Mesh {
+        self.mesh.clone()
+    }
+    fn instances(&self) -> Vec<Instance> {
+        self.instances.clone()
+    }
+}
+
+// Submits every instanced mesh in a single call, for backends that can draw instances natively.
+pub struct InstanceRenderer {}
+
+impl InstanceRenderer {
+    pub fn new() -> Self {
+        InstanceRenderer {}
+    }
+}
+
+impl<B, D> Renderer<B, D> for InstanceRenderer
+where
+    D: Data + GetInstances,
+    B: Backend<D> + backend::RenderInstances,
+{
+    fn render(&mut self, backend: &mut B, data: &D) {
+        for instanced in data.instance_iter() {
+            let instances = instanced.instances();
+            if !instances.is_empty() {
+                backend.queue_render_instances(instanced.mesh(), instances);
+            }
+        }
+    }
+}
+
+// Fallback for backends that only implement `RenderMesh`: every instance becomes one
+// `queue_render` call with the tint baked into the vertex colors.
+pub struct ExpandedInstanceRenderer {}
+
+impl ExpandedInstanceRenderer {
+    pub fn new() -> Self {
+        ExpandedInstanceRenderer {}
+    }
+}
+
+impl<B, D> Renderer<B, D> for ExpandedInstanceRenderer
+where
+    D: Data + GetInstances,
+    B: Backend<D> + mesh_renderer::backend::RenderMesh,
+{
+    fn render(&mut self, backend: &mut B, data: &D) {
+        for instanced in data.instance_iter() {
+            expand(backend, instanced.mesh(), instanced.instances());
+        }
+    }
+}
+
+// Backends implementing `RenderInstances` without native support can forward to this.
+pub fn expand<B>(backend: &mut B, mesh: Mesh, instances: Vec<Instance>)
+where
+    B: mesh_renderer::backend::RenderMesh + ?Sized,
+{
+    for instance in instances {
+        backend.queue_render(instance.transform, tinted(&mesh, &instance.tint));
+    }
+}
+
+fn tinted(mesh: &Mesh, tint: &Vector4<f32>) -> Mesh {
+    if *tint == Vector4::new(1.0, 1.0, 1.0, 1.0) {
+        return mesh.clone();
+    }
+    let tint_vertex = |v: Vertex| Vertex {
+        color: v.color.component_mul(tint),
+        ..v
+    };
+    Mesh {
+        triangles: mesh.triangles
+            .iter()
+            .map(|t| Triangle::new(tint_vertex(t.v1), tint_vertex(t.v2), tint_vertex(t.v3)))
+            .collect(),
+    }
+}
+
+pub mod backend {
+    use super::{Instance, Mesh};
+
+    pub trait RenderInstances {
+        fn queue_render_instances(&mut self, Mesh, Vec<Instance>);
+    }
+}
@@ -5,16 +5,34 @@ extern crate mursten;
 extern crate nalgebra;
 extern crate rustyline;
 
+pub mod batching;
+pub mod bounds;
+pub mod bvh;
 pub mod camera;
+pub mod camera_controller;
+pub mod camera_rig;
 //pub mod cursive_renderer;
+pub mod ecs;
 pub mod events;
 pub mod geometry;
+pub mod image_output;
 pub mod input;
+pub mod instancing;
 pub mod light;
+pub mod light_bake;
+pub mod material;
 pub mod mesh_renderer;
 pub mod midi;
+pub mod mock_backend;
 pub mod properties;
 pub mod property_editor;
 pub mod repl;
+pub mod scene;
+pub mod scene_file;
+pub mod software_renderer;
+pub mod svg_renderer;
+pub mod terminal_renderer;
+pub mod text;
+pub mod texture;
 pub mod time;
 
@@ -1,20 +1,417 @@
+use alga::linear::Transformation;
 use mursten::{Backend, Data, Updater};
 use nalgebra::*;
+use std::f32;
+use std::ops;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Light {
-    pub point: Point3<f32>,
+    pub kind: LightKind,
     pub color: Vector3<f32>,
-    pub strength: f32, // Cambiar esto por un coeficiente que relacione la distancia con la intensidad
+    pub intensity: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    // Shines in every direction from a point.
+    Point {
+        position: Point3<f32>,
+        attenuation: Attenuation,
+    },
+    // Parallel rays coming from very far away, like sunlight. `direction` is where the light
+    // travels to.
+    Directional { direction: Vector3<f32> },
+    // A point light limited to a cone. Angles are measured from the direction to the border of
+    // the cone, in radians: it is fully lit inside `inner_angle` and fades out up to
+    // `outer_angle`.
+    Spot {
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+        attenuation: Attenuation,
+    },
+    // Lights everything the same, no matter where it is or where it faces.
+    Ambient,
+    // Ambient light with the color of the light coming from the sky, along `up`, and `ground`
+    // coming from below. Surfaces get a blend of both depending on where they face.
+    Hemisphere {
+        up: Vector3<f32>,
+        ground: Vector3<f32>,
+    },
+}
+
+// How the light of point and spot lights weakens with the distance. Every model multiplies the
+// intensity of the light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attenuation {
+    // One over `constant + linear * d + quadratic * d²`. Never reaches zero.
+    Polynomial {
+        constant: f32,
+        linear: f32,
+        quadratic: f32,
+    },
+    // One over `d²`, as real lights do, and nothing at all past `range`.
+    InverseSquare { range: f32 },
+    // Inverse square, smoothly faded so it reaches zero at `range` instead of cutting off.
+    Smooth { range: f32 },
+}
+
+// Inverse square models are measured from this distance at least, so they stay finite at the
+// position of the light.
+const MIN_DISTANCE: f32 = 0.01;
+
+impl Attenuation {
+    pub fn polynomial(constant: f32, linear: f32, quadratic: f32) -> Self {
+        Attenuation::Polynomial {
+            constant,
+            linear,
+            quadratic,
+        }
+    }
+    pub fn inverse_square(range: f32) -> Self {
+        Attenuation::InverseSquare { range }
+    }
+    pub fn smooth(range: f32) -> Self {
+        Attenuation::Smooth { range }
+    }
+
+    // Fraction of the intensity left at `distance` from the light.
+    pub fn factor(&self, distance: f32) -> f32 {
+        match *self {
+            Attenuation::Polynomial {
+                constant,
+                linear,
+                quadratic,
+            } => {
+                let d = constant + linear * distance + quadratic * distance * distance;
+                if d > 0.0 {
+                    1.0 / d
+                } else {
+                    1.0
+                }
+            }
+            Attenuation::InverseSquare { range } => {
+                if distance > range {
+                    0.0
+                } else {
+                    1.0 / distance.max(MIN_DISTANCE).powi(2)
+                }
+            }
+            Attenuation::Smooth { range } => {
+                let ratio = distance / range;
+                let window = (1.0 - ratio.powi(4)).max(0.0).min(1.0);
+                window * window / distance.max(MIN_DISTANCE).powi(2)
+            }
+        }
+    }
+
+    // Distance past which the light has no effect at all, if any.
+    pub fn range(&self) -> Option<f32> {
+        match *self {
+            Attenuation::Polynomial { .. } => None,
+            Attenuation::InverseSquare { range } | Attenuation::Smooth { range } => Some(range),
+        }
+    }
+}
+
+// `1 / (1 + d²)`.
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self::polynomial(1.0, 0.0, 1.0)
+    }
+}
+
+// A point being lit, as seen from `eye`. The normal does not need to be normalized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surface {
+    pub point: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub eye: Point3<f32>,
+    // Blinn-Phong exponent of the highlights.
+    pub shininess: f32,
+    // Lit on the side that faces the eye, instead of only on the side the normal points to.
+    pub double_sided: bool,
+}
+
+impl Surface {
+    pub fn new(point: Point3<f32>, normal: Vector3<f32>, eye: Point3<f32>) -> Self {
+        Surface {
+            point,
+            normal,
+            eye,
+            shininess: 32.0,
+            double_sided: false,
+        }
+    }
+    pub fn shininess(self, shininess: f32) -> Self {
+        Self { shininess, ..self }
+    }
+    pub fn double_sided(self, double_sided: bool) -> Self {
+        Self {
+            double_sided,
+            ..self
+        }
+    }
+}
+
+// Light reaching a surface, to be multiplied by the diffuse and specular colors of its material
+// and added together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contribution {
+    pub diffuse: Vector3<f32>,
+    pub specular: Vector3<f32>,
+}
+
+impl Contribution {
+    pub fn zero() -> Self {
+        Contribution {
+            diffuse: Vector3::zeros(),
+            specular: Vector3::zeros(),
+        }
+    }
+    pub fn color(&self, diffuse: &Vector3<f32>, specular: &Vector3<f32>) -> Vector3<f32> {
+        diffuse.component_mul(&self.diffuse) + specular.component_mul(&self.specular)
+    }
+}
+
+impl ops::Add for Contribution {
+    type Output = Contribution;
+    fn add(self, other: Contribution) -> Contribution {
+        Contribution {
+            diffuse: self.diffuse + other.diffuse,
+            specular: self.specular + other.specular,
+        }
+    }
+}
+
+// Sum of what every light contributes to the surface. Shared by the CPU code and backends, so
+// everything is lit the same way.
+pub fn contribution(lights: &[Light], surface: &Surface) -> Contribution {
+    lights
+        .iter()
+        .fold(Contribution::zero(), |sum, light| sum + light.contribution(surface))
 }
 
 impl Light {
-    pub fn new(point: Point3<f32>, color: Vector3<f32>, strength: f32) -> Self {
-        Self { point, color, strength }
+    pub fn point(position: Point3<f32>, color: Vector3<f32>, intensity: f32) -> Self {
+        let kind = LightKind::Point {
+            position,
+            attenuation: Attenuation::default(),
+        };
+        Self::new(kind, color, intensity)
+    }
+    pub fn directional(direction: Vector3<f32>, color: Vector3<f32>, intensity: f32) -> Self {
+        Self::new(LightKind::Directional { direction }, color, intensity)
+    }
+    // Cone of 30 degrees fading out up to 45, change it with `cone`.
+    pub fn spot(
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        color: Vector3<f32>,
+        intensity: f32,
+    ) -> Self {
+        let kind = LightKind::Spot {
+            position,
+            direction,
+            inner_angle: f32::consts::FRAC_PI_6,
+            outer_angle: f32::consts::FRAC_PI_4,
+            attenuation: Attenuation::default(),
+        };
+        Self::new(kind, color, intensity)
+    }
+    pub fn ambient(color: Vector3<f32>, intensity: f32) -> Self {
+        Self::new(LightKind::Ambient, color, intensity)
+    }
+    // `sky` is the color of the light coming from above.
+    pub fn hemisphere(sky: Vector3<f32>, ground: Vector3<f32>, intensity: f32) -> Self {
+        let kind = LightKind::Hemisphere {
+            up: Vector3::y(),
+            ground,
+        };
+        Self::new(kind, sky, intensity)
+    }
+    pub fn new(kind: LightKind, color: Vector3<f32>, intensity: f32) -> Self {
+        Self {
+            kind,
+            color,
+            intensity,
+        }
+    }
+
+    // Only changes spot lights.
+    pub fn cone(mut self, inner: f32, outer: f32) -> Self {
+        if let LightKind::Spot {
+            ref mut inner_angle,
+            ref mut outer_angle,
+            ..
+        } = self.kind
+        {
+            *inner_angle = inner;
+            *outer_angle = outer;
+        }
+        self
+    }
+
+    // Only changes point and spot lights.
+    pub fn attenuation(mut self, model: Attenuation) -> Self {
+        match self.kind {
+            LightKind::Point {
+                ref mut attenuation,
+                ..
+            }
+            | LightKind::Spot {
+                ref mut attenuation,
+                ..
+            } => *attenuation = model,
+            _ => {}
+        }
+        self
+    }
+
+    pub fn position(&self) -> Option<Point3<f32>> {
+        match self.kind {
+            LightKind::Point { position, .. } | LightKind::Spot { position, .. } => Some(position),
+            _ => None,
+        }
+    }
+
+    // The same light, placed by `transform`.
+    pub fn transform(&self, transform: &Matrix4<f32>) -> Light {
+        let point = |p: Point3<f32>| transform.transform_point(&p);
+        let vector = |v: Vector3<f32>| {
+            let v = transform.transform_vector(&v);
+            v.try_normalize(0.0).unwrap_or(v)
+        };
+        let kind = match self.kind {
+            LightKind::Point {
+                position,
+                attenuation,
+            } => LightKind::Point {
+                position: point(position),
+                attenuation,
+            },
+            LightKind::Directional { direction } => LightKind::Directional {
+                direction: vector(direction),
+            },
+            LightKind::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+                attenuation,
+            } => LightKind::Spot {
+                position: point(position),
+                direction: vector(direction),
+                inner_angle,
+                outer_angle,
+                attenuation,
+            },
+            LightKind::Ambient => LightKind::Ambient,
+            LightKind::Hemisphere { up, ground } => LightKind::Hemisphere {
+                up: vector(up),
+                ground,
+            },
+        };
+        Light { kind, ..*self }
+    }
+
+    // Light arriving at `point` from a single direction: the unit vector pointing towards the
+    // light and how much of its color reaches the point. None for ambient lights, see
+    // `ambient_at`, and for points out of the cone of a spot light.
+    pub fn incoming(&self, point: &Point3<f32>) -> Option<(Vector3<f32>, f32)> {
+        match self.kind {
+            LightKind::Point {
+                position,
+                attenuation,
+            } => {
+                let to_light = position - point;
+                let amount = self.intensity * attenuation.factor(to_light.norm());
+                Some((to_light.try_normalize(0.0)?, amount))
+            }
+            LightKind::Directional { direction } => {
+                Some((-direction.try_normalize(0.0)?, self.intensity))
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+                attenuation,
+            } => {
+                let to_light = position - point;
+                let l = to_light.try_normalize(0.0)?;
+                let axis = direction.try_normalize(0.0)?;
+                let (inner, outer) = (inner_angle.cos(), outer_angle.cos());
+                let cosine = (-l).dot(&axis);
+                let cone = if cosine >= inner {
+                    1.0
+                } else if cosine > outer && inner > outer {
+                    let t = (cosine - outer) / (inner - outer);
+                    t * t * (3.0 - 2.0 * t)
+                } else {
+                    return None;
+                };
+                Some((l, self.intensity * attenuation.factor(to_light.norm()) * cone))
+            }
+            LightKind::Ambient | LightKind::Hemisphere { .. } => None,
+        }
+    }
+
+    // Light reaching a surface facing `normal` from every direction at once.
+    pub fn ambient_at(&self, normal: &Vector3<f32>) -> Vector3<f32> {
+        match self.kind {
+            LightKind::Ambient => self.color * self.intensity,
+            LightKind::Hemisphere { up, ground } => {
+                let cosine = match (normal.try_normalize(0.0), up.try_normalize(0.0)) {
+                    (Some(n), Some(up)) => n.dot(&up),
+                    _ => 0.0,
+                };
+                let sky = (cosine + 1.0) / 2.0;
+                (self.color * sky + ground * (1.0 - sky)) * self.intensity
+            }
+            _ => Vector3::zeros(),
+        }
+    }
+
+    // Lambert diffuse and Blinn-Phong specular light this light gives to the surface. Ambient
+    // lights only add to the diffuse part.
+    pub fn contribution(&self, surface: &Surface) -> Contribution {
+        let normal = match surface.normal.try_normalize(0.0) {
+            Some(normal) => normal,
+            None => return Contribution::zero(),
+        };
+        // The back of a double sided surface is lit as if it was its front
+        let normal = if surface.double_sided && normal.dot(&(surface.eye - surface.point)) < 0.0 {
+            -normal
+        } else {
+            normal
+        };
+        let mut contribution = Contribution {
+            diffuse: self.ambient_at(&normal),
+            specular: Vector3::zeros(),
+        };
+        let (to_light, amount) = match self.incoming(&surface.point) {
+            Some(incoming) => incoming,
+            None => return contribution,
+        };
+        let facing = normal.dot(&to_light).max(0.0);
+        contribution.diffuse += self.color * (facing * amount);
+
+        let to_eye = (surface.eye - surface.point).try_normalize(0.0);
+        if let Some(half) = to_eye.and_then(|e| (e + to_light).try_normalize(0.0)) {
+            let highlight = normal.dot(&half).max(0.0).powf(surface.shininess);
+            // Light coming from behind the surface gives no highlight
+            if highlight.is_finite() && facing > 0.0 {
+                contribution.specular = self.color * (highlight * amount);
+            }
+        }
+        contribution
     }
 }
 
 pub trait GetLights {
-    fn get_light(&self) -> Light;
+    fn get_lights(&self) -> Vec<Light>;
 }
 
 pub struct LightUpdater {}
@@ -31,14 +428,87 @@ where
     B: Backend<D> + backend::SetLights,
 {
     fn update(&mut self, backend: &mut B, data: &mut D) {
-        let light = data.get_light();
-        backend.set_light(light);
+        let lights = data.get_lights();
+        backend.set_lights(lights);
     }
 }
 
 pub mod backend {
     pub trait SetLights {
-        fn set_light(&mut self, light: super::Light);
+        // Replaces every light given before.
+        fn set_lights(&mut self, lights: Vec<super::Light>);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_behind_a_double_sided_surface_does_not_reach_its_visible_side() {
+        let white = Vector3::repeat(1.0);
+        let eye = Point3::new(0.0, 0.0, 5.0);
+        let surface = Surface::new(Point3::origin(), Vector3::z(), eye).double_sided(true);
+        let behind = Light::point(Point3::new(0.0, 0.0, -5.0), white, 1.0);
+        let lit = behind.contribution(&surface);
+        assert_eq!(lit.diffuse, Vector3::zeros());
+        assert_eq!(lit.specular, Vector3::zeros());
+
+        // Seen from behind the light is in front again, whichever way the normal points
+        let back_eye = Point3::new(0.0, 0.0, -5.0);
+        let flipped = Surface::new(Point3::origin(), Vector3::z(), back_eye).double_sided(true);
+        let front = Light::point(Point3::new(0.0, 0.0, 5.0), white, 1.0);
+        assert!(behind.contribution(&flipped).diffuse.x > 0.0);
+        assert_eq!(front.contribution(&flipped).diffuse, Vector3::zeros());
+    }
+
+    #[test]
+    fn inverse_square_attenuation_stops_at_its_range() {
+        let model = Attenuation::inverse_square(5.0);
+        assert_eq!(model.range(), Some(5.0));
+        assert_eq!(model.factor(2.0), 0.25);
+        assert_eq!(model.factor(5.0), 1.0 / 25.0);
+        assert_eq!(model.factor(5.001), 0.0);
+        assert!(model.factor(0.0).is_finite());
+
+        let polynomial = Attenuation::default();
+        assert_eq!(polynomial.range(), None);
+        assert!(polynomial.factor(1000.0) > 0.0);
+    }
+
+    #[test]
+    fn smooth_attenuation_fades_to_zero_at_its_range() {
+        let model = Attenuation::smooth(4.0);
+        assert_eq!(model.factor(4.0), 0.0);
+        assert_eq!(model.factor(6.0), 0.0);
+        assert!(model.factor(3.99) > 0.0);
+        let samples: Vec<f32> = (1..40).map(|i| model.factor(i as f32 * 0.1)).collect();
+        assert!(samples.windows(2).all(|w| w[0] > w[1]));
+        // Close to the light it is almost inverse square
+        assert!((model.factor(0.1) * 0.01 - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn spot_lights_fade_between_their_cone_edges() {
+        let (inner, outer) = (0.3f32, 0.5f32);
+        let spot = Light::spot(Point3::new(0.0, 5.0, 0.0), -Vector3::y(), Vector3::repeat(1.0), 1.0)
+            .cone(inner, outer)
+            .attenuation(Attenuation::polynomial(1.0, 0.0, 0.0));
+        // Diffuse light on the ground at `angle` from the axis of the cone
+        let diffuse = |angle: f32| {
+            let point = Point3::new(5.0 * angle.tan(), 0.0, 0.0);
+            let surface = Surface::new(point, Vector3::y(), Point3::new(0.0, 10.0, 0.0));
+            spot.contribution(&surface).diffuse.x
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+
+        assert!(close(diffuse(0.0), 1.0));
+        assert!(close(diffuse(inner - 0.01), (inner - 0.01).cos()));
+        assert_eq!(diffuse(outer + 0.01), 0.0);
+        assert!(diffuse(outer - 0.01) > 0.0);
+        let angle = (inner + outer) / 2.0;
+        let t = (angle.cos() - outer.cos()) / (inner.cos() - outer.cos());
+        assert!(close(diffuse(angle), t * t * (3.0 - 2.0 * t) * angle.cos()));
+        assert!(diffuse(angle) < angle.cos());
+    }
+}
@@ -0,0 +1,294 @@
+use nalgebra::*;
+use std::collections::HashMap;
+use std::f32;
+
+use super::bounds::Ray;
+use super::bvh::MeshBvh;
+use super::geometry::Mesh;
+use super::light::{self, Light, Surface};
+use super::material::Material;
+
+// Darkening of the vertices that are hidden by nearby parts of the same mesh, found by casting
+// rays over the hemisphere around their normal. Only ambient light is occluded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmbientOcclusion {
+    // Rays cast from every vertex.
+    pub samples: usize,
+    // Geometry farther away than this does not occlude, in world units.
+    pub distance: f32,
+    // How much of the ambient light a fully occluded vertex loses, from 0 to 1.
+    pub strength: f32,
+}
+
+impl AmbientOcclusion {
+    pub fn new(samples: usize, distance: f32) -> Self {
+        AmbientOcclusion {
+            samples,
+            distance,
+            strength: 1.0,
+        }
+    }
+    pub fn strength(self, strength: f32) -> Self {
+        Self { strength, ..self }
+    }
+}
+
+// Lighting baked into the vertex colors, for backends that only draw `Vertex::color`. Shading
+// is the same as `light::contribution`, evaluated once per vertex with the lights and the eye in
+// world coordinates.
+//
+//     let lit = LightBake::new()
+//         .material(Material::new().specular(Vector3::new(0.4, 0.4, 0.4), 16.0))
+//         .smooth_normals(true)
+//         .ambient_occlusion(AmbientOcclusion::new(32, 2.0))
+//         .bake(&mesh, &transform, &lights, &eye);
+#[derive(Debug, Clone)]
+pub struct LightBake {
+    // Everything but the texture is taken into account.
+    pub material: Material,
+    // Added to the ambient lights, like `SoftwareRenderer::ambient`.
+    pub ambient: f32,
+    // Vertices at the same position share the average normal of their triangles, instead of
+    // each one taking the normal of its own triangle.
+    pub smooth_normals: bool,
+    pub occlusion: Option<AmbientOcclusion>,
+}
+
+impl LightBake {
+    pub fn new() -> Self {
+        LightBake {
+            material: Material::default(),
+            ambient: 0.0,
+            smooth_normals: false,
+            occlusion: None,
+        }
+    }
+    pub fn material(self, material: Material) -> Self {
+        Self { material, ..self }
+    }
+    pub fn ambient(self, ambient: f32) -> Self {
+        Self { ambient, ..self }
+    }
+    pub fn smooth_normals(self, smooth_normals: bool) -> Self {
+        Self {
+            smooth_normals,
+            ..self
+        }
+    }
+    pub fn ambient_occlusion(self, occlusion: AmbientOcclusion) -> Self {
+        Self {
+            occlusion: Some(occlusion),
+            ..self
+        }
+    }
+
+    // Copy of `mesh` with the shaded colors. Positions stay in mesh coordinates, so it is still
+    // drawn with `transform`.
+    pub fn bake(
+        &self,
+        mesh: &Mesh,
+        transform: &Matrix4<f32>,
+        lights: &[Light],
+        eye: &Point3<f32>,
+    ) -> Mesh {
+        if self.material.unlit {
+            return self.material.bake(mesh.clone());
+        }
+        let world = mesh.clone().transform(transform);
+        let normals = self.normals(&world);
+        let occluder = self.occlusion.map(|occlusion| {
+            // Rays start a little above the surface so they do not hit it
+            let bias = world.bounds().extents().norm() * 1e-4;
+            (MeshBvh::new(&world), occlusion, bias)
+        });
+
+        let e = self.material.emissive;
+        let emissive = Vector4::new(e.x, e.y, e.z, 0.0);
+        let mut baked = mesh.clone();
+        for (i, triangle) in baked.triangles.iter_mut().enumerate() {
+            let w = &world.triangles[i];
+            let positions = [w.v1.position, w.v2.position, w.v3.position];
+            let centroid = Point3::from_coordinates(
+                (positions[0].coords + positions[1].coords + positions[2].coords) / 3.0,
+            );
+            let mut vertices = [&mut triangle.v1, &mut triangle.v2, &mut triangle.v3];
+            for (k, vertex) in vertices.iter_mut().enumerate() {
+                let point = positions[k];
+                let mut normal = normals[i * 3 + k];
+                // The side facing the eye is the one that gets lit, as in the renderers
+                if self.material.double_sided && normal.dot(&(eye - point)) < 0.0 {
+                    normal = -normal;
+                }
+                let surface = Surface::new(point, normal, *eye)
+                    .shininess(self.material.shininess)
+                    .double_sided(self.material.double_sided);
+                let contribution = light::contribution(lights, &surface);
+
+                let ambient = lights
+                    .iter()
+                    .fold(Vector3::zeros(), |sum, l| sum + l.ambient_at(&normal));
+                let visibility = match occluder {
+                    Some((ref bvh, ref occlusion, bias)) => {
+                        // Pulled into the triangle, so vertices lying on another surface do not
+                        // see it from exactly on top
+                        let inside = point + (centroid - point) * 1e-3;
+                        ambient_visibility(bvh, occlusion, &inside, &normal, bias)
+                    }
+                    None => 1.0,
+                };
+                let diffuse = contribution.diffuse - ambient
+                    + (ambient + Vector3::repeat(self.ambient)) * visibility;
+
+                let color = self.material.surface_color(&vertex.color);
+                let lit = Vector3::new(color.x, color.y, color.z).component_mul(&diffuse)
+                    + self.material.specular.component_mul(&contribution.specular);
+                vertex.color = Vector4::new(lit.x, lit.y, lit.z, color.w) + emissive;
+            }
+        }
+        baked
+    }
+
+    // Three normals per triangle, in world coordinates and weighted by the triangle area.
+    fn normals(&self, world: &Mesh) -> Vec<Vector3<f32>> {
+        let faces: Vec<Vector3<f32>> = world
+            .triangles
+            .iter()
+            .map(|t| (t.v2.position - t.v1.position).cross(&(t.v3.position - t.v1.position)))
+            .collect();
+        if !self.smooth_normals {
+            return faces.iter().flat_map(|n| vec![*n, *n, *n]).collect();
+        }
+        // Adding zero turns negative zeros into positive ones, so both get the same key
+        let key = |p: &Point3<f32>| {
+            [
+                (p.x + 0.0).to_bits(),
+                (p.y + 0.0).to_bits(),
+                (p.z + 0.0).to_bits(),
+            ]
+        };
+        let mut sums: HashMap<[u32; 3], Vector3<f32>> = HashMap::new();
+        for (t, face) in world.triangles.iter().zip(faces.iter()) {
+            for v in &[t.v1, t.v2, t.v3] {
+                *sums.entry(key(&v.position)).or_insert_with(Vector3::zeros) += face;
+            }
+        }
+        world
+            .triangles
+            .iter()
+            .flat_map(|t| vec![t.v1.position, t.v2.position, t.v3.position])
+            .map(|p| sums[&key(&p)])
+            .collect()
+    }
+}
+
+// Lighting with the default bake options: a white, double sided material without ambient
+// occlusion.
+pub fn bake_lighting(
+    mesh: &Mesh,
+    transform: &Matrix4<f32>,
+    lights: &[Light],
+    eye: &Point3<f32>,
+) -> Mesh {
+    LightBake::new().bake(mesh, transform, lights, eye)
+}
+
+// Fraction of the ambient light that reaches `point`. Directions follow a spiral over the
+// hemisphere, denser towards the normal, so results are the same on every bake.
+fn ambient_visibility(
+    bvh: &MeshBvh,
+    occlusion: &AmbientOcclusion,
+    point: &Point3<f32>,
+    normal: &Vector3<f32>,
+    bias: f32,
+) -> f32 {
+    let n = match normal.try_normalize(0.0) {
+        Some(n) => n,
+        None => return 1.0,
+    };
+    if occlusion.samples == 0 {
+        return 1.0;
+    }
+    let axis = if n.x.abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    let tangent = n.cross(&axis).normalize();
+    let bitangent = n.cross(&tangent);
+    let origin = point + n * bias;
+    let golden_angle = f32::consts::PI * (3.0 - 5.0f32.sqrt());
+
+    let mut hits = 0;
+    for i in 0..occlusion.samples {
+        let r = ((i as f32 + 0.5) / occlusion.samples as f32).sqrt();
+        let phi = i as f32 * golden_angle;
+        let direction = tangent * (r * phi.cos()) + bitangent * (r * phi.sin())
+            + n * (1.0 - r * r).max(0.0).sqrt();
+        if bvh.raycast(&Ray::new(origin, direction), occlusion.distance).is_some() {
+            hits += 1;
+        }
+    }
+    1.0 - occlusion.strength * hits as f32 / occlusion.samples as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alga::linear::Transformation;
+    use geometry::{Triangle, Vertex};
+
+    fn v(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex::at(Point3::new(x, y, z))
+    }
+
+    #[test]
+    fn occluded_vertices_bake_darker() {
+        // A floor facing up, with a wall standing on its edge at x = 0
+        let room = Mesh {
+            triangles: vec![
+                Triangle::new(v(0.0, 0.0, 0.0), v(0.0, 0.0, 4.0), v(4.0, 0.0, 4.0)),
+                Triangle::new(v(0.0, 0.0, 0.0), v(4.0, 0.0, 4.0), v(4.0, 0.0, 0.0)),
+                Triangle::new(v(0.0, 0.0, 0.0), v(0.0, 4.0, 0.0), v(0.0, 4.0, 4.0)),
+                Triangle::new(v(0.0, 0.0, 0.0), v(0.0, 4.0, 4.0), v(0.0, 0.0, 4.0)),
+            ],
+        };
+        let baked = LightBake::new()
+            .material(Material::new().double_sided(false))
+            .ambient(1.0)
+            .ambient_occlusion(AmbientOcclusion::new(64, 2.0))
+            .bake(&room, &Matrix4::identity(), &[], &Point3::new(10.0, 10.0, 10.0));
+
+        let corner = baked.triangles[0].v1.color.x;
+        let open = baked.triangles[0].v3.color.x;
+        assert!(corner < open, "{} should be darker than {}", corner, open);
+        assert!((open - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn lambert_term_matches_the_light_contribution() {
+        let mesh = Mesh {
+            triangles: vec![Triangle::new(v(0.0, 0.0, 0.0), v(1.0, 0.0, 1.0), v(1.0, 0.0, 0.0))],
+        };
+        let transform = Matrix4::new_translation(&Vector3::new(2.0, 1.0, 0.0));
+        let lights = [
+            Light::point(Point3::new(3.0, 4.0, 1.0), Vector3::new(1.0, 0.8, 0.6), 2.0),
+            Light::directional(Vector3::new(1.0, -1.0, 0.0), Vector3::repeat(1.0), 0.5),
+        ];
+        let eye = Point3::new(0.0, 6.0, 6.0);
+        let bake = LightBake::new();
+        let baked = bake.bake(&mesh, &transform, &lights, &eye);
+
+        let triangle = &mesh.triangles[0];
+        let source = [triangle.v1, triangle.v2, triangle.v3];
+        let lit = [baked.triangles[0].v1, baked.triangles[0].v2, baked.triangles[0].v3];
+        for (vertex, lit) in source.iter().zip(lit.iter()) {
+            let point = transform.transform_point(&vertex.position);
+            let surface = Surface::new(point, Vector3::y(), eye)
+                .shininess(bake.material.shininess)
+                .double_sided(bake.material.double_sided);
+            let expected = light::contribution(&lights, &surface).diffuse;
+            assert!((lit.color.xyz() - expected).norm() < 1e-5);
+            assert!(expected.x > 0.0);
+        }
+    }
+}
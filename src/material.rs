@@ -0,0 +1,152 @@
+use nalgebra::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use super::geometry::Mesh;
+use super::mesh_renderer::IntoMesh;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    // Multiplies the vertex colors.
+    pub base_color: Vector4<f32>,
+    // Added after lighting, so it shows even in the dark.
+    pub emissive: Vector3<f32>,
+    pub specular: Vector3<f32>,
+    pub shininess: f32,
+    // Multiplies the alpha of the vertex colors.
+    pub opacity: f32,
+    // Name under which the backend knows the texture.
+    pub texture: Option<String>,
+    // Skips lighting, colors are drawn as they are.
+    pub unlit: bool,
+    // When false, triangles seen from behind are not drawn. Front faces are counter clockwise.
+    pub double_sided: bool,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            base_color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            emissive: Vector3::zeros(),
+            specular: Vector3::zeros(),
+            shininess: 32.0,
+            opacity: 1.0,
+            texture: None,
+            unlit: false,
+            double_sided: true,
+        }
+    }
+}
+
+impl Material {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn base_color(self, base_color: Vector4<f32>) -> Self {
+        Self { base_color, ..self }
+    }
+    pub fn emissive(self, emissive: Vector3<f32>) -> Self {
+        Self { emissive, ..self }
+    }
+    pub fn specular(self, specular: Vector3<f32>, shininess: f32) -> Self {
+        Self {
+            specular,
+            shininess,
+            ..self
+        }
+    }
+    pub fn opacity(self, opacity: f32) -> Self {
+        Self { opacity, ..self }
+    }
+    pub fn texture(self, name: &str) -> Self {
+        Self {
+            texture: Some(name.to_string()),
+            ..self
+        }
+    }
+    pub fn unlit(self, unlit: bool) -> Self {
+        Self { unlit, ..self }
+    }
+    pub fn double_sided(self, double_sided: bool) -> Self {
+        Self {
+            double_sided,
+            ..self
+        }
+    }
+
+    pub fn is_translucent(&self) -> bool {
+        self.opacity < 1.0 || self.base_color.w < 1.0
+    }
+
+    // Vertex color after applying the base color and opacity, before lighting.
+    pub fn surface_color(&self, color: &Vector4<f32>) -> Vector4<f32> {
+        let mut color = color.component_mul(&self.base_color);
+        color.w *= self.opacity;
+        color
+    }
+
+    // Equal materials have equal keys. Used as the default `IntoMesh::batch_key`.
+    pub fn batch_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let scalars = [self.shininess, self.opacity];
+        let floats = self.base_color
+            .iter()
+            .chain(self.emissive.iter())
+            .chain(self.specular.iter())
+            .chain(scalars.iter());
+        for f in floats {
+            f.to_bits().hash(&mut hasher);
+        }
+        self.texture.hash(&mut hasher);
+        self.unlit.hash(&mut hasher);
+        self.double_sided.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Folds base color, opacity and emission into the vertex colors, for backends that only
+    // understand `RenderMesh`. Specular, texture and face culling are lost.
+    pub fn bake(&self, mesh: Mesh) -> Mesh {
+        let emissive = Vector4::new(self.emissive.x, self.emissive.y, self.emissive.z, 0.0);
+        Mesh {
+            triangles: mesh.triangles
+                .into_iter()
+                .map(|mut t| {
+                    for v in [&mut t.v1, &mut t.v2, &mut t.v3].iter_mut() {
+                        v.color = self.surface_color(&v.color) + emissive;
+                    }
+                    t
+                })
+                .collect(),
+        }
+    }
+}
+
+// Attaches a material to something that has none of its own.
+pub struct MaterialMesh<M> {
+    pub mesh: M,
+    pub material: Material,
+}
+
+impl<M> MaterialMesh<M>
+where
+    M: IntoMesh,
+{
+    pub fn new(mesh: M, material: Material) -> Self {
+        MaterialMesh { mesh, material }
+    }
+}
+
+impl<M> IntoMesh for MaterialMesh<M>
+where
+    M: IntoMesh,
+{
+    fn transform(&self) -> Matrix4<f32> {
+        self.mesh.transform()
+    }
+    fn mesh(&self) -> Mesh {
+        self.mesh.mesh()
+    }
+    fn material(&self) -> Material {
+        self.material.clone()
+    }
+}
@@ -1,30 +1,400 @@
+use alga::linear::Transformation;
 use mursten::{Backend, Data, Renderer};
 use nalgebra::*;
+use std::cmp;
 use std::vec;
 
-use super::geometry::Mesh;
+use super::bounds::{Aabb, Frustum};
+use super::camera::{self, Camera, GetCamera, GetCameras};
+use super::geometry::{Mesh, Triangle};
+use super::material::Material;
 
+pub struct MeshRenderer<C = NoCulling, S = NoSorting, V = SingleView, M = MeshesOnly> {
+    culling: C,
+    sorting: S,
+    views: V,
+    submission: M,
+    stats: RenderStats,
+}
 
-pub struct MeshRenderer {}
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RenderStats {
+    pub submitted: usize,
+    pub culled: usize,
+}
 
-impl MeshRenderer {
+impl MeshRenderer<NoCulling, NoSorting> {
     pub fn new() -> Self {
-        MeshRenderer {}
+        MeshRenderer {
+            culling: NoCulling,
+            sorting: NoSorting,
+            views: SingleView,
+            submission: MeshesOnly,
+            stats: RenderStats::default(),
+        }
+    }
+}
+
+impl MeshRenderer<FrustumCulling, NoSorting> {
+    // Skips the meshes whose bounds fall outside of what the camera sees. Requires the data to
+    // provide the camera through `GetCamera`.
+    pub fn with_frustum_culling() -> Self {
+        MeshRenderer {
+            culling: FrustumCulling::new(),
+            sorting: NoSorting,
+            views: SingleView,
+            submission: MeshesOnly,
+            stats: RenderStats::default(),
+        }
+    }
+}
+
+impl<C, V, M> MeshRenderer<C, NoSorting, V, M> {
+    // Submits opaque meshes front to back and translucent ones, those with a translucent
+    // material or any vertex alpha below one, back to front after them. Requires the data to
+    // provide the camera through `GetCamera`.
+    pub fn depth_sorted(self) -> MeshRenderer<C, DepthSorting, V, M> {
+        MeshRenderer {
+            culling: self.culling,
+            sorting: DepthSorting::new(),
+            views: self.views,
+            submission: self.submission,
+            stats: self.stats,
+        }
+    }
+}
+
+impl<C, V, M> MeshRenderer<C, DepthSorting, V, M> {
+    // Also orders the triangles inside each translucent mesh back to front.
+    pub fn sort_translucent_triangles(mut self, sort_triangles: bool) -> Self {
+        self.sorting.sort_triangles = sort_triangles;
+        self
+    }
+}
+
+impl<C, S, M> MeshRenderer<C, S, SingleView, M> {
+    // Renders the meshes once for every camera given by `camera::GetCameras`, instead of once for
+    // the camera of `GetCamera`. Before each pass the backend receives the camera and its
+    // viewport, and culling and sorting use that camera.
+    pub fn per_viewport(self) -> MeshRenderer<C, S, PerViewport, M> {
+        MeshRenderer {
+            culling: self.culling,
+            sorting: self.sorting,
+            views: PerViewport,
+            submission: self.submission,
+            stats: self.stats,
+        }
+    }
+}
+
+impl<C, S, V> MeshRenderer<C, S, V, MeshesOnly> {
+    // Sends the material of each mesh along with it, through `backend::RenderMaterialMesh`.
+    pub fn with_materials(self) -> MeshRenderer<C, S, V, WithMaterials> {
+        MeshRenderer {
+            culling: self.culling,
+            sorting: self.sorting,
+            views: self.views,
+            submission: WithMaterials,
+            stats: self.stats,
+        }
+    }
+}
+
+impl<C, S, V, M> MeshRenderer<C, S, V, M> {
+    // Counters of the last rendered frame, added up over all viewports.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    // Whether the current camera sees each of the meshes.
+    fn cull(&mut self, meshes: &[FrameMesh]) -> Vec<bool>
+    where
+        C: Culling,
+    {
+        let visible: Vec<bool> = meshes
+            .iter()
+            .map(|&(ref transform, _, _, ref bounds)| self.culling.is_visible(transform, bounds))
+            .collect();
+        self.stats.culled += visible.iter().filter(|&&v| !v).count();
+        visible
+    }
+
+    fn submit<B>(&mut self, backend: &mut B, visible: Vec<Submitted>)
+    where
+        S: Sorting,
+        M: Submission<B>,
+    {
+        for (transform, mesh, material) in self.sorting.sort(visible) {
+            self.submission.submit(backend, transform, mesh, material);
+            self.stats.submitted += 1;
+        }
     }
 }
 
-impl<B, D> Renderer<B, D> for MeshRenderer
+// A mesh built once per frame, with its transform, material and bounds in mesh coordinates.
+type FrameMesh = (Matrix4<f32>, Mesh, Material, Aabb);
+
+fn frame_meshes<D: GetMeshes>(data: &D) -> Vec<FrameMesh> {
+    data.mesh_iter()
+        .map(|m| {
+            let mesh = m.mesh();
+            let bounds = mesh.bounds();
+            (m.transform(), mesh, m.material(), bounds)
+        })
+        .collect()
+}
+
+pub struct SingleView;
+
+pub struct PerViewport;
+
+impl<B, D, C, S, M> Renderer<B, D> for MeshRenderer<C, S, SingleView, M>
 where
     D: Data + GetMeshes,
-    B: Backend<D> + backend::RenderMesh,
+    B: Backend<D>,
+    C: Culling + Prepare<D>,
+    S: Sorting + Prepare<D>,
+    M: Submission<B>,
+{
+    fn render(&mut self, backend: &mut B, data: &D) {
+        self.culling.prepare(data);
+        self.sorting.prepare(data);
+        self.stats = RenderStats::default();
+        let meshes = frame_meshes(data);
+        let visible = self.cull(&meshes);
+        let visible = meshes
+            .into_iter()
+            .zip(visible)
+            .filter(|&(_, visible)| visible)
+            .map(|((transform, mesh, material, _), _)| (transform, mesh, material))
+            .collect();
+        self.submit(backend, visible);
+    }
+}
+
+impl<B, D, C, S, M> Renderer<B, D> for MeshRenderer<C, S, PerViewport, M>
+where
+    D: Data + GetMeshes + GetCameras,
+    B: Backend<D> + camera::backend::SetViewport,
+    C: Culling,
+    S: Sorting,
+    M: Submission<B>,
 {
     fn render(&mut self, backend: &mut B, data: &D) {
-        for mesh in data.mesh_iter() {
-            backend.queue_render(mesh.transform(), mesh.mesh());
+        self.stats = RenderStats::default();
+        // Built once and shared by every viewport
+        let meshes = frame_meshes(data);
+        for camera in data.camera_iter() {
+            backend.set_viewport(camera.transform, &camera.camera, camera.viewport);
+            self.culling.set_camera(&camera.transform, &camera.camera);
+            self.sorting.set_camera(&camera.transform, &camera.camera);
+            let visible = self.cull(&meshes);
+            let visible = meshes
+                .iter()
+                .zip(visible)
+                .filter(|&(_, visible)| visible)
+                .map(|(&(transform, ref mesh, ref material, _), _)| {
+                    (transform, mesh.clone(), material.clone())
+                })
+                .collect();
+            self.submit(backend, visible);
+        }
+    }
+}
+
+// How each mesh reaches the backend.
+pub trait Submission<B> {
+    fn submit(&self, backend: &mut B, transform: Matrix4<f32>, mesh: Mesh, material: Material);
+}
+
+pub struct MeshesOnly;
+
+impl<B> Submission<B> for MeshesOnly
+where
+    B: backend::RenderMesh,
+{
+    fn submit(&self, backend: &mut B, transform: Matrix4<f32>, mesh: Mesh, _: Material) {
+        backend.queue_render(transform, mesh);
+    }
+}
+
+pub struct WithMaterials;
+
+impl<B> Submission<B> for WithMaterials
+where
+    B: backend::RenderMaterialMesh,
+{
+    fn submit(&self, backend: &mut B, transform: Matrix4<f32>, mesh: Mesh, material: Material) {
+        backend.queue_render_material(transform, mesh, material);
+    }
+}
+
+// How culling and sorting strategies get their camera when rendering a single view.
+pub trait Prepare<D> {
+    fn prepare(&mut self, data: &D);
+}
+
+pub trait Culling {
+    fn set_camera(&mut self, view: &Matrix4<f32>, camera: &Camera);
+    // `bounds` are given in mesh coordinates, placed by `transform`.
+    fn is_visible(&self, transform: &Matrix4<f32>, bounds: &Aabb) -> bool;
+}
+
+pub struct NoCulling;
+
+impl<D> Prepare<D> for NoCulling {
+    fn prepare(&mut self, _: &D) {}
+}
+
+impl Culling for NoCulling {
+    fn set_camera(&mut self, _: &Matrix4<f32>, _: &Camera) {}
+    fn is_visible(&self, _: &Matrix4<f32>, _: &Aabb) -> bool {
+        true
+    }
+}
+
+pub struct FrustumCulling {
+    frustum: Option<Frustum>,
+}
+
+impl FrustumCulling {
+    pub fn new() -> Self {
+        FrustumCulling { frustum: None }
+    }
+}
+
+impl<D> Prepare<D> for FrustumCulling
+where
+    D: GetCamera,
+{
+    fn prepare(&mut self, data: &D) {
+        if let Some((view, camera)) = data.find_camera() {
+            self.set_camera(&view, camera);
+        }
+    }
+}
+
+impl Culling for FrustumCulling {
+    fn set_camera(&mut self, view: &Matrix4<f32>, camera: &Camera) {
+        self.frustum = Some(Frustum::from_matrix(&(camera.projection() * view)));
+    }
+    fn is_visible(&self, transform: &Matrix4<f32>, bounds: &Aabb) -> bool {
+        match self.frustum {
+            Some(ref frustum) => {
+                bounds.is_empty() || frustum.intersects_aabb(&bounds.transform(transform))
+            }
+            None => true,
+        }
+    }
+}
+
+// A mesh ready to be submitted, with its transform and material.
+pub type Submitted = (Matrix4<f32>, Mesh, Material);
+
+pub trait Sorting {
+    fn set_camera(&mut self, view: &Matrix4<f32>, camera: &Camera);
+    fn sort(&self, meshes: Vec<Submitted>) -> Vec<Submitted>;
+}
+
+pub struct NoSorting;
+
+impl<D> Prepare<D> for NoSorting {
+    fn prepare(&mut self, _: &D) {}
+}
+
+impl Sorting for NoSorting {
+    fn set_camera(&mut self, _: &Matrix4<f32>, _: &Camera) {}
+    fn sort(&self, meshes: Vec<Submitted>) -> Vec<Submitted> {
+        meshes
+    }
+}
+
+pub struct DepthSorting {
+    view: Matrix4<f32>,
+    sort_triangles: bool,
+}
+
+impl DepthSorting {
+    pub fn new() -> Self {
+        DepthSorting {
+            view: Matrix4::identity(),
+            sort_triangles: false,
+        }
+    }
+
+    // Distance from the camera position to a point given in mesh coordinates.
+    fn distance(&self, transform: &Matrix4<f32>, point: &Point3<f32>) -> f32 {
+        (self.view * transform).transform_point(point).coords.norm()
+    }
+
+    fn mesh_distance(&self, transform: &Matrix4<f32>, mesh: &Mesh) -> f32 {
+        let bounds = mesh.bounds();
+        if bounds.is_empty() {
+            0.0
+        } else {
+            self.distance(transform, &bounds.center())
+        }
+    }
+}
+
+impl<D> Prepare<D> for DepthSorting
+where
+    D: GetCamera,
+{
+    fn prepare(&mut self, data: &D) {
+        if let Some((view, camera)) = data.find_camera() {
+            self.set_camera(&view, camera);
+        }
+    }
+}
+
+impl Sorting for DepthSorting {
+    fn set_camera(&mut self, view: &Matrix4<f32>, _: &Camera) {
+        self.view = *view;
+    }
+    fn sort(&self, meshes: Vec<Submitted>) -> Vec<Submitted> {
+        let (mut opaque, mut translucent): (Vec<_>, Vec<_>) = meshes
+            .into_iter()
+            .map(|(transform, mesh, material)| {
+                (self.mesh_distance(&transform, &mesh), (transform, mesh, material))
+            })
+            .partition(|&(_, (_, ref mesh, ref material))| !is_translucent(mesh, material));
+
+        opaque.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(cmp::Ordering::Equal));
+        translucent.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(cmp::Ordering::Equal));
+
+        if self.sort_triangles {
+            for &mut (_, (ref transform, ref mut mesh, _)) in translucent.iter_mut() {
+                let mut triangles: Vec<(f32, Triangle)> = mesh.triangles
+                    .iter()
+                    .map(|t| {
+                        let centroid = Point3::from_coordinates(
+                            (t.v1.position.coords + t.v2.position.coords + t.v3.position.coords)
+                                / 3.0,
+                        );
+                        (self.distance(transform, &centroid), *t)
+                    })
+                    .collect();
+                triangles.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(cmp::Ordering::Equal));
+                mesh.triangles = triangles.into_iter().map(|(_, t)| t).collect();
+            }
         }
+
+        opaque
+            .into_iter()
+            .chain(translucent)
+            .map(|(_, submitted)| submitted)
+            .collect()
     }
 }
 
+fn is_translucent(mesh: &Mesh, material: &Material) -> bool {
+    material.is_translucent()
+        || mesh.triangles
+            .iter()
+            .any(|t| t.v1.color.w < 1.0 || t.v2.color.w < 1.0 || t.v3.color.w < 1.0)
+}
+
 pub trait GetMeshes {
     fn mesh_iter<'a>(&'a self) -> vec::IntoIter<&IntoMesh>;
 }
@@ -35,6 +405,20 @@ pub trait IntoMesh {
         Matrix4::identity()
     }
     fn mesh(&self) -> Mesh;
+    fn material(&self) -> Material {
+        Material::default()
+    }
+    // Identifies the material state of the mesh. Meshes with equal keys can be drawn together,
+    // see `batching::BatchRenderer`.
+    fn batch_key(&self) -> u64 {
+        self.material().batch_key()
+    }
+}
+
+impl IntoMesh for Mesh {
+    fn mesh(&self) -> Mesh {
+        self.clone()
+    }
 }
 
 pub mod backend {
@@ -43,5 +427,133 @@ pub mod backend {
     pub trait RenderMesh {
         fn queue_render(&mut self, Matrix4<f32>, super::Mesh);
     }
+
+    // `RenderMesh` for backends that shade with the material of the mesh.
+    pub trait RenderMaterialMesh {
+        fn queue_render_material(&mut self, Matrix4<f32>, super::Mesh, super::Material);
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::{Cameras, ViewportCamera};
+    use geometry::Vertex;
+    use mock_backend::MockBackend;
+    use std::cell::Cell;
+
+    fn square_at(z: f32) -> Mesh {
+        let v = |x, y| Vertex::at(Point3::new(x, y, z));
+        Mesh {
+            triangles: vec![Triangle::new(v(0.0, 0.0), v(1.0, 0.0), v(0.0, 1.0))],
+        }
+    }
+
+    #[test]
+    fn translucent_materials_are_sorted_back_to_front_after_opaque_meshes() {
+        let glass = Material::new().opacity(0.5);
+        let meshes = vec![
+            (Matrix4::identity(), square_at(-2.0), glass.clone()),
+            (Matrix4::identity(), square_at(-8.0), Material::new()),
+            (Matrix4::identity(), square_at(-6.0), glass.clone()),
+            (Matrix4::identity(), square_at(-4.0), Material::new()),
+        ];
+        let sorted = DepthSorting::new().sort(meshes);
+        let order: Vec<(f32, bool)> = sorted
+            .iter()
+            .map(|&(_, ref mesh, ref material)| {
+                (mesh.triangles[0].v1.position.z, material.is_translucent())
+            })
+            .collect();
+        assert_eq!(
+            order,
+            vec![(-4.0, false), (-8.0, false), (-6.0, true), (-2.0, true)]
+        );
+    }
+
+    // Counts how many times it was built.
+    struct Counted {
+        position: Vector3<f32>,
+        builds: Cell<usize>,
+    }
+
+    impl IntoMesh for Counted {
+        fn transform(&self) -> Matrix4<f32> {
+            Matrix4::new_translation(&self.position)
+        }
+        fn mesh(&self) -> Mesh {
+            self.builds.set(self.builds.get() + 1);
+            square_at(0.0)
+        }
+    }
+
+    struct Stage {
+        camera: Camera,
+        cameras: Cameras,
+        meshes: Vec<Counted>,
+    }
+
+    impl Data for Stage {}
+
+    impl GetCamera for Stage {
+        fn get_camera<'a>(&'a self) -> (Matrix4<f32>, &'a Camera) {
+            (Matrix4::identity(), &self.camera)
+        }
+    }
+
+    impl GetCameras for Stage {
+        fn camera_iter<'a>(&'a self) -> vec::IntoIter<&'a ViewportCamera> {
+            self.cameras.camera_iter()
+        }
+    }
+
+    impl GetMeshes for Stage {
+        fn mesh_iter<'a>(&'a self) -> vec::IntoIter<&IntoMesh> {
+            let meshes: Vec<&IntoMesh> = self.meshes.iter().map(|m| m as &IntoMesh).collect();
+            meshes.into_iter()
+        }
+    }
+
+    // A mesh in front of the camera and one far off to its side.
+    fn stage() -> Stage {
+        let counted = |x| Counted {
+            position: Vector3::new(x, 0.0, -5.0),
+            builds: Cell::new(0),
+        };
+        let mut cameras = Cameras::new();
+        cameras.add(ViewportCamera::new("first", Camera::perspective()));
+        cameras.add(ViewportCamera::new("second", Camera::perspective()));
+        Stage {
+            camera: Camera::perspective(),
+            cameras,
+            meshes: vec![counted(0.0), counted(100.0)],
+        }
+    }
+
+    #[test]
+    fn meshes_out_of_view_are_not_queued() {
+        let stage = stage();
+        let mut backend = MockBackend::new();
+        let mut renderer = MeshRenderer::with_frustum_culling();
+        renderer.render(&mut backend, &stage);
+
+        let rendered = backend.rendered_meshes();
+        assert_eq!(rendered.len(), 1);
+        assert_eq!(*rendered[0].0, stage.meshes[0].transform());
+        assert_eq!(renderer.stats(), RenderStats { submitted: 1, culled: 1 });
+    }
+
+    #[test]
+    fn meshes_are_built_once_per_frame_for_all_viewports() {
+        let stage = stage();
+        let mut backend = MockBackend::new();
+        let mut renderer = MeshRenderer::with_frustum_culling().per_viewport();
+        renderer.render(&mut backend, &stage);
+
+        assert_eq!(backend.rendered_meshes().len(), 2);
+        assert_eq!(renderer.stats(), RenderStats { submitted: 2, culled: 2 });
+        for mesh in stage.meshes.iter() {
+            assert_eq!(mesh.builds.get(), 1);
+        }
+    }
+}
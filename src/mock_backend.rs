@@ -0,0 +1,355 @@
+use mursten::{Backend, Data, RenderChain, UpdateChain};
+use nalgebra::*;
+use std::collections::VecDeque;
+
+use super::camera::{self, Camera, Viewport};
+use super::geometry::Mesh;
+use super::image_output;
+use super::input::{self, KeyboardEvent, MouseEvent, ResizeEvent};
+use super::instancing::{self, Instance};
+use super::light::{self, Light};
+use super::material::Material;
+use super::mesh_renderer;
+use super::software_renderer::Framebuffer;
+
+// Every call received by the `MockBackend`, in order.
+#[derive(Debug, Clone)]
+pub enum Call {
+    SetCamera {
+        transform: Matrix4<f32>,
+        camera: Camera,
+    },
+    SetViewport {
+        transform: Matrix4<f32>,
+        camera: Camera,
+        viewport: Viewport,
+    },
+    SetLights(Vec<Light>),
+    QueueRender(Matrix4<f32>, Mesh),
+    QueueRenderInstances(Mesh, Vec<Instance>),
+    QueueRenderMaterial(Matrix4<f32>, Mesh, Material),
+    DrainKeyboardEvents,
+    DrainMouseEvents,
+    DrainResizeEvents,
+    Frame,
+    Quit,
+}
+
+// Backend for tests. It implements every backend trait of the crate, records all calls and
+// answers `drain_events` with the batches that were scripted beforehand, one per call.
+//
+//     let mut backend = MockBackend::new();
+//     backend.script_keyboard(vec![KeyboardEvent::Pressed(Key::A, KeyModifiers {})]);
+//     KeyboardUpdater::new().update(&mut backend, &mut data);
+//     MeshRenderer::new().render(&mut backend, &data);
+//     assert_eq!(backend.rendered_meshes().len(), 3);
+pub struct MockBackend {
+    calls: Vec<Call>,
+    keyboard_events: VecDeque<Vec<KeyboardEvent>>,
+    mouse_events: VecDeque<Vec<MouseEvent>>,
+    resize_events: VecDeque<Vec<ResizeEvent>>,
+    framebuffer: Framebuffer,
+    quit_requested: bool,
+    max_frames: usize,
+    frames_run: usize,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend {
+            calls: Vec::new(),
+            keyboard_events: VecDeque::new(),
+            mouse_events: VecDeque::new(),
+            resize_events: VecDeque::new(),
+            framebuffer: Framebuffer::new(1, 1),
+            quit_requested: false,
+            max_frames: 1,
+            frames_run: 0,
+        }
+    }
+
+    // Number of update and render passes `run` makes unless `quit` is called earlier.
+    pub fn max_frames(self, max_frames: usize) -> Self {
+        Self { max_frames, ..self }
+    }
+
+    // Framebuffer handed out through `FrameSource`.
+    pub fn framebuffer(self, framebuffer: Framebuffer) -> Self {
+        Self {
+            framebuffer,
+            ..self
+        }
+    }
+
+    pub fn script_keyboard(&mut self, events: Vec<KeyboardEvent>) {
+        self.keyboard_events.push_back(events);
+    }
+
+    pub fn script_mouse(&mut self, events: Vec<MouseEvent>) {
+        self.mouse_events.push_back(events);
+    }
+
+    pub fn script_resize(&mut self, events: Vec<ResizeEvent>) {
+        self.resize_events.push_back(events);
+    }
+
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    pub fn clear_calls(&mut self) {
+        self.calls.clear();
+    }
+
+    pub fn cameras(&self) -> Vec<(Matrix4<f32>, &Camera)> {
+        self.calls
+            .iter()
+            .filter_map(|c| match *c {
+                Call::SetCamera {
+                    transform,
+                    ref camera,
+                } => Some((transform, camera)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn viewports(&self) -> Vec<Viewport> {
+        self.calls
+            .iter()
+            .filter_map(|c| match *c {
+                Call::SetViewport { viewport, .. } => Some(viewport),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The lights given on each call to `set_lights`.
+    pub fn lights(&self) -> Vec<&[Light]> {
+        self.calls
+            .iter()
+            .filter_map(|c| match *c {
+                Call::SetLights(ref lights) => Some(&lights[..]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn rendered_meshes(&self) -> Vec<(&Matrix4<f32>, &Mesh)> {
+        self.calls
+            .iter()
+            .filter_map(|c| match *c {
+                Call::QueueRender(ref transform, ref mesh) => Some((transform, mesh)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    pub fn quit_count(&self) -> usize {
+        self.calls
+            .iter()
+            .filter(|c| match **c {
+                Call::Quit => true,
+                _ => false,
+            })
+            .count()
+    }
+
+    pub fn frames_run(&self) -> usize {
+        self.frames_run
+    }
+}
+
+impl<D> Backend<D> for MockBackend
+where
+    D: Data,
+{
+    fn run(
+        mut self,
+        mut render_chain: RenderChain<Self, D>,
+        mut update_chain: UpdateChain<Self, D>,
+        mut data: D,
+    ) -> D {
+        while self.frames_run < self.max_frames && !self.quit_requested {
+            update_chain.update(&mut self, &mut data);
+            render_chain.render(&mut self, &data);
+            self.frames_run += 1;
+        }
+        data
+    }
+
+    fn quit(&mut self) {
+        self.quit_requested = true;
+        self.calls.push(Call::Quit);
+    }
+}
+
+impl camera::backend::SetCamera for MockBackend {
+    fn set_camera(&mut self, transform: Matrix4<f32>, camera: &Camera) {
+        self.calls.push(Call::SetCamera {
+            transform,
+            camera: camera.clone(),
+        });
+    }
+}
+
+impl camera::backend::SetViewport for MockBackend {
+    fn set_viewport(&mut self, transform: Matrix4<f32>, camera: &Camera, viewport: Viewport) {
+        self.calls.push(Call::SetViewport {
+            transform,
+            camera: camera.clone(),
+            viewport,
+        });
+    }
+}
+
+impl light::backend::SetLights for MockBackend {
+    fn set_lights(&mut self, lights: Vec<Light>) {
+        self.calls.push(Call::SetLights(lights));
+    }
+}
+
+impl mesh_renderer::backend::RenderMesh for MockBackend {
+    fn queue_render(&mut self, transform: Matrix4<f32>, mesh: Mesh) {
+        self.calls.push(Call::QueueRender(transform, mesh));
+    }
+}
+
+impl mesh_renderer::backend::RenderMaterialMesh for MockBackend {
+    fn queue_render_material(&mut self, transform: Matrix4<f32>, mesh: Mesh, material: Material) {
+        self.calls.push(Call::QueueRenderMaterial(transform, mesh, material));
+    }
+}
+
+impl instancing::backend::RenderInstances for MockBackend {
+    fn queue_render_instances(&mut self, mesh: Mesh, instances: Vec<Instance>) {
+        self.calls.push(Call::QueueRenderInstances(mesh, instances));
+    }
+}
+
+impl input::backend::KeyboardEventSource for MockBackend {
+    fn drain_events(&mut self) -> Vec<KeyboardEvent> {
+        self.calls.push(Call::DrainKeyboardEvents);
+        self.keyboard_events.pop_front().unwrap_or_default()
+    }
+}
+
+impl input::backend::MouseEventSource for MockBackend {
+    fn drain_events(&mut self) -> Vec<MouseEvent> {
+        self.calls.push(Call::DrainMouseEvents);
+        self.mouse_events.pop_front().unwrap_or_default()
+    }
+}
+
+impl input::backend::ResizeEventSource for MockBackend {
+    fn drain_events(&mut self) -> Vec<ResizeEvent> {
+        self.calls.push(Call::DrainResizeEvents);
+        self.resize_events.pop_front().unwrap_or_default()
+    }
+}
+
+impl image_output::backend::FrameSource for MockBackend {
+    fn frame(&mut self) -> &Framebuffer {
+        self.calls.push(Call::Frame);
+        &self.framebuffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::{CameraUpdater, Cameras, GetCamera, GetCameras, Orthographic, ViewportCamera};
+    use geometry::Triangle;
+    use mesh_renderer::{GetMeshes, IntoMesh, MeshRenderer};
+    use mursten::{Renderer, Updater};
+    use std::vec;
+
+    struct Game {
+        view: Matrix4<f32>,
+        camera: Camera,
+        cameras: Cameras,
+        meshes: Vec<Mesh>,
+    }
+
+    impl Data for Game {}
+
+    impl GetCamera for Game {
+        fn get_camera<'a>(&'a self) -> (Matrix4<f32>, &'a Camera) {
+            (self.view, &self.camera)
+        }
+    }
+
+    impl GetCameras for Game {
+        fn camera_iter<'a>(&'a self) -> vec::IntoIter<&'a ViewportCamera> {
+            self.cameras.camera_iter()
+        }
+    }
+
+    impl GetMeshes for Game {
+        fn mesh_iter<'a>(&'a self) -> vec::IntoIter<&IntoMesh> {
+            let meshes: Vec<&IntoMesh> = self.meshes.iter().map(|m| m as &IntoMesh).collect();
+            meshes.into_iter()
+        }
+    }
+
+    fn game() -> Game {
+        let mut cameras = Cameras::new();
+        let left = Viewport::new(0.0, 0.0, 0.5, 1.0);
+        let right = Viewport::new(0.5, 0.0, 0.5, 1.0);
+        cameras.add(ViewportCamera::new("left", Camera::perspective()).viewport(left));
+        cameras.add(ViewportCamera::new("right", Camera::orthographic()).viewport(right));
+        Game {
+            view: Matrix4::new_translation(&Vector3::new(0.0, 0.0, -3.0)),
+            camera: Camera::new(Orthographic::size(4.0, 2.0)),
+            cameras,
+            meshes: vec![Mesh {
+                triangles: vec![Triangle::default()],
+            }],
+        }
+    }
+
+    #[test]
+    fn records_the_camera_that_was_set() {
+        let mut game = game();
+        let mut backend = MockBackend::new();
+        CameraUpdater::new().update(&mut backend, &mut game);
+        assert_eq!(backend.cameras(), vec![(game.view, &game.camera)]);
+    }
+
+    #[test]
+    fn records_every_pass_of_a_per_viewport_render() {
+        let game = game();
+        let mut backend = MockBackend::new();
+        MeshRenderer::new()
+            .per_viewport()
+            .render(&mut backend, &game);
+
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 4);
+        let passes = [
+            ("left", Camera::perspective()),
+            ("right", Camera::orthographic()),
+        ];
+        for (pass, &(name, ref lens)) in calls.chunks(2).zip(passes.iter()) {
+            match pass[0] {
+                Call::SetViewport {
+                    ref camera,
+                    viewport,
+                    ..
+                } => {
+                    assert_eq!(camera, lens);
+                    assert_eq!(viewport, game.cameras.get(name).unwrap().viewport);
+                }
+                ref call => panic!("expected a viewport, got {:?}", call),
+            }
+            match pass[1] {
+                Call::QueueRender(_, ref mesh) => assert_eq!(mesh.triangles.len(), 1),
+                ref call => panic!("expected a mesh, got {:?}", call),
+            }
+        }
+    }
+}
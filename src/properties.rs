@@ -14,7 +14,7 @@ pub trait Property<'a> {
     fn get(&self) -> Value;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Float(f32),
     Integer(i32),
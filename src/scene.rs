@@ -1,3 +1,373 @@
-use mursten::{Backend, Data, Renderer};
+use nalgebra::*;
+use std::cell::Cell;
+use std::vec;
 
+use super::camera::Camera;
+use super::geometry::Mesh;
+use super::light::{GetLights, Light};
+use super::material::Material;
+use super::mesh_renderer::{GetMeshes, IntoMesh};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self::default()
+    }
+    pub fn translation(self, translation: Vector3<f32>) -> Self {
+        Self { translation, ..self }
+    }
+    pub fn rotation(self, rotation: UnitQuaternion<f32>) -> Self {
+        Self { rotation, ..self }
+    }
+    pub fn scale(self, scale: Vector3<f32>) -> Self {
+        Self { scale, ..self }
+    }
+    pub fn uniform_scale(self, scale: f32) -> Self {
+        Self {
+            scale: Vector3::new(scale, scale, scale),
+            ..self
+        }
+    }
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation) * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SceneError {
+    NodeNotFound(NodeId),
+    Cycle(NodeId),
+}
+
+pub struct Node {
+    pub name: Option<String>,
+    pub transform: Transform,
+    pub visible: bool,
+    // Placed by the node transform, like the mesh.
+    pub light: Option<Light>,
+    pub camera: Option<Camera>,
+    content: Option<Content>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: Cell<Matrix4<f32>>,
+    dirty: Cell<bool>,
+}
+
+struct Content {
+    mesh: Box<IntoMesh>,
+    world: Cell<Matrix4<f32>>,
+}
+
+impl IntoMesh for Content {
+    fn transform(&self) -> Matrix4<f32> {
+        self.world.get() * self.mesh.transform()
+    }
+    fn mesh(&self) -> Mesh {
+        self.mesh.mesh()
+    }
+    fn material(&self) -> Material {
+        self.mesh.material()
+    }
+    fn batch_key(&self) -> u64 {
+        self.mesh.batch_key()
+    }
+}
+
+impl Node {
+    pub fn new() -> Self {
+        Node {
+            name: None,
+            transform: Transform::identity(),
+            visible: true,
+            light: None,
+            camera: None,
+            content: None,
+            parent: None,
+            children: Vec::new(),
+            world: Cell::new(Matrix4::identity()),
+            dirty: Cell::new(true),
+        }
+    }
+    pub fn named(self, name: &str) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            ..self
+        }
+    }
+    pub fn transform(self, transform: Transform) -> Self {
+        Self { transform, ..self }
+    }
+    pub fn mesh<M>(self, mesh: M) -> Self
+    where
+        M: IntoMesh + 'static,
+    {
+        Self {
+            content: Some(Content {
+                mesh: Box::new(mesh),
+                world: Cell::new(Matrix4::identity()),
+            }),
+            ..self
+        }
+    }
+    pub fn light(self, light: Light) -> Self {
+        Self {
+            light: Some(light),
+            ..self
+        }
+    }
+    pub fn camera(self, camera: Camera) -> Self {
+        Self {
+            camera: Some(camera),
+            ..self
+        }
+    }
+    pub fn content(&self) -> Option<&IntoMesh> {
+        self.content.as_ref().map(|c| c.mesh.as_ref())
+    }
+    pub fn set_content<M>(&mut self, mesh: M)
+    where
+        M: IntoMesh + 'static,
+    {
+        self.content = Some(Content {
+            mesh: Box::new(mesh),
+            world: Cell::new(self.world.get()),
+        });
+    }
+    pub fn clear_content(&mut self) {
+        self.content = None;
+    }
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+pub struct Scene {
+    nodes: Vec<Option<Node>>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Scene {
+            nodes: Vec::new(),
+            roots: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, node: Node) -> NodeId {
+        let id = self.insert(node);
+        self.roots.push(id);
+        id
+    }
+
+    pub fn add_child(&mut self, parent: NodeId, node: Node) -> Result<NodeId, SceneError> {
+        if self.get(parent).is_none() {
+            return Err(SceneError::NodeNotFound(parent));
+        }
+        let id = self.insert(node);
+        self.node_mut(id).parent = Some(parent);
+        self.node_mut(parent).children.push(id);
+        Ok(id)
+    }
+
+    // Removes the node and all its descendants.
+    pub fn remove(&mut self, id: NodeId) -> Result<(), SceneError> {
+        let parent = match self.get(id) {
+            Some(node) => node.parent,
+            None => return Err(SceneError::NodeNotFound(id)),
+        };
+        self.detach(id, parent);
+        for descendant in self.descendants(id) {
+            self.nodes[descendant.0] = None;
+        }
+        Ok(())
+    }
+
+    // Moves the node under a new parent, or makes it a root when `parent` is `None`. The local
+    // transform is kept, so the node moves along with its new parent.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<(), SceneError> {
+        let old_parent = match self.get(id) {
+            Some(node) => node.parent,
+            None => return Err(SceneError::NodeNotFound(id)),
+        };
+        if let Some(p) = parent {
+            if self.get(p).is_none() {
+                return Err(SceneError::NodeNotFound(p));
+            }
+            if self.descendants(id).contains(&p) {
+                return Err(SceneError::Cycle(p));
+            }
+        }
+        self.detach(id, old_parent);
+        match parent {
+            Some(p) => self.node_mut(p).children.push(id),
+            None => self.roots.push(id),
+        }
+        self.node_mut(id).parent = parent;
+        self.mark_dirty(id);
+        Ok(())
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id.0).and_then(|n| n.as_ref())
+    }
+
+    // Any change made through the returned reference may affect the node transform, so its
+    // subtree will have its world matrices recomputed.
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        if self.get(id).is_some() {
+            self.mark_dirty(id);
+        }
+        self.nodes.get_mut(id.0).and_then(|n| n.as_mut())
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.iter()
+            .find(|&id| self.node(id).name.as_ref().map_or(false, |n| n == name))
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    // All nodes, depth first, parents before their children.
+    pub fn iter(&self) -> vec::IntoIter<NodeId> {
+        let mut ids = Vec::new();
+        for &root in &self.roots {
+            ids.extend(self.descendants(root));
+        }
+        ids.into_iter()
+    }
+
+    pub fn world_transform(&self, id: NodeId) -> Option<Matrix4<f32>> {
+        self.get(id)?;
+        self.refresh();
+        Some(self.node(id).world.get())
+    }
+
+    // World matrices are cached and only recomputed for nodes whose transform, or whose
+    // ancestors' transforms, changed since the last refresh.
+    pub fn refresh(&self) {
+        for &root in &self.roots {
+            self.refresh_node(root, &Matrix4::identity(), false);
+        }
+    }
+
+    fn refresh_node(&self, id: NodeId, parent_world: &Matrix4<f32>, parent_changed: bool) {
+        let node = self.node(id);
+        let changed = parent_changed || node.dirty.get();
+        if changed {
+            let world = parent_world * node.transform.to_matrix();
+            node.world.set(world);
+            node.dirty.set(false);
+            if let Some(ref content) = node.content {
+                content.world.set(world);
+            }
+        }
+        let world = node.world.get();
+        for &child in &node.children {
+            self.refresh_node(child, &world, changed);
+        }
+    }
+
+    fn insert(&mut self, node: Node) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Some(node));
+        id
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        self.nodes[id.0].as_ref().unwrap()
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes[id.0].as_mut().unwrap()
+    }
+
+    fn detach(&mut self, id: NodeId, parent: Option<NodeId>) {
+        match parent {
+            Some(p) => self.node_mut(p).children.retain(|&c| c != id),
+            None => self.roots.retain(|&r| r != id),
+        }
+    }
+
+    fn mark_dirty(&self, id: NodeId) {
+        self.node(id).dirty.set(true);
+    }
+
+    fn descendants(&self, id: NodeId) -> Vec<NodeId> {
+        let mut result = Vec::new();
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            result.push(current);
+            stack.extend(self.node(current).children.iter().rev());
+        }
+        result
+    }
+
+    fn is_visible(&self, id: NodeId) -> bool {
+        let node = self.node(id);
+        node.visible && node.parent.map_or(true, |parent| self.is_visible(parent))
+    }
+
+    fn visible_meshes<'a>(&'a self, id: NodeId, meshes: &mut Vec<&'a IntoMesh>) {
+        let node = self.node(id);
+        if !node.visible {
+            return;
+        }
+        if let Some(ref content) = node.content {
+            meshes.push(content);
+        }
+        for &child in &node.children {
+            self.visible_meshes(child, meshes);
+        }
+    }
+}
+
+impl GetMeshes for Scene {
+    fn mesh_iter<'a>(&'a self) -> vec::IntoIter<&IntoMesh> {
+        self.refresh();
+        let mut meshes = Vec::new();
+        for &root in &self.roots {
+            self.visible_meshes(root, &mut meshes);
+        }
+        meshes.into_iter()
+    }
+}
+
+// Lights of the visible nodes, in world coordinates.
+impl GetLights for Scene {
+    fn get_lights(&self) -> Vec<Light> {
+        self.refresh();
+        self.iter()
+            .filter(|&id| self.is_visible(id))
+            .filter_map(|id| {
+                let node = self.node(id);
+                node.light.as_ref().map(|light| light.transform(&node.world.get()))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,1140 @@
+use nalgebra::*;
+use std::fmt::{self, Write};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::camera::{Camera, Lens, Orthographic, Perspective};
+use super::geometry::{Mesh, Triangle, Vertex};
+use super::light::{Attenuation, Light, LightKind};
+use super::properties::{Properties, Value};
+use super::scene::{Node, NodeId, Scene, Transform};
+
+// A scene as it is stored on disk. The format is a tree of blocks, one field per line:
+//
+//     node "ship" {
+//         translation 0 1 0
+//         rotation 0 0 0 1
+//         scale 1 1 1
+//         mesh "ship_hull"
+//         light {
+//             point 0 2 0
+//             color 1 0.8 0.6
+//             intensity 4
+//         }
+//         node "cockpit" {
+//             mesh {
+//                 triangle {
+//                     vertex { position 0 0 0 color 1 1 1 1 texture 0 0 }
+//                     ...
+//                 }
+//             }
+//         }
+//     }
+//     property "speed" float 0.5
+//
+// Rotations are quaternions written as `i j k w`. Everything after a `#` is a comment. Strings
+// may contain the escapes `\\`, `\"`, `\n`, `\t` and `\r`.
+pub struct SceneDocument {
+    pub nodes: Vec<NodeDescription>,
+    pub properties: Vec<PropertyDescription>,
+}
+
+// Positions are those of the name in the file, or 0 for values that were not read from a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyDescription {
+    pub name: String,
+    pub value: Value,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub struct NodeDescription {
+    pub name: Option<String>,
+    pub transform: Transform,
+    pub visible: bool,
+    pub mesh: Option<MeshSource>,
+    pub light: Option<Light>,
+    pub camera: Option<Camera>,
+    pub children: Vec<NodeDescription>,
+}
+
+#[derive(Debug, Clone)]
+pub enum MeshSource {
+    Reference {
+        name: String,
+        line: usize,
+        column: usize,
+    },
+    Inline(Mesh),
+}
+
+// Errors without a position in the file, like those reading it, have line and column 0.
+#[derive(Debug)]
+pub struct LoadError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: LoadErrorKind,
+}
+
+#[derive(Debug)]
+pub enum LoadErrorKind {
+    Io(io::Error),
+    UnexpectedEnd,
+    UnexpectedToken { expected: &'static str, found: String },
+    UnknownField { block: &'static str, field: String },
+    TypeMismatch { field: String, expected: &'static str, found: String },
+    UnresolvedMesh(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: ", self.line, self.column)?;
+        }
+        match self.kind {
+            LoadErrorKind::Io(ref err) => write!(f, "{}", err),
+            LoadErrorKind::UnexpectedEnd => write!(f, "unexpected end of file"),
+            LoadErrorKind::UnexpectedToken {
+                expected,
+                ref found,
+            } => write!(f, "expected {}, found {}", expected, found),
+            LoadErrorKind::UnknownField { block, ref field } => {
+                write!(f, "unknown field `{}` in {}", field, block)
+            }
+            LoadErrorKind::TypeMismatch {
+                ref field,
+                expected,
+                ref found,
+            } => write!(f, "`{}` expects {}, found {}", field, expected, found),
+            LoadErrorKind::UnresolvedMesh(ref name) => write!(f, "unknown mesh `{}`", name),
+        }
+    }
+}
+
+impl SceneDocument {
+    pub fn new() -> Self {
+        SceneDocument {
+            nodes: Vec::new(),
+            properties: Vec::new(),
+        }
+    }
+
+    // `reference` chooses, for each node with a mesh, whether the mesh is stored by name. Meshes
+    // that are not referenced are stored inline, with their own transform already applied.
+    pub fn from_scene<F>(scene: &Scene, mut reference: F) -> Self
+    where
+        F: FnMut(NodeId, &Node) -> Option<String>,
+    {
+        let nodes = scene
+            .roots()
+            .iter()
+            .map(|&id| describe_node(scene, id, &mut reference))
+            .collect();
+        SceneDocument {
+            nodes,
+            properties: Vec::new(),
+        }
+    }
+
+    // Builds the node hierarchy. `resolve` provides the meshes stored by reference.
+    pub fn to_scene<F>(&self, mut resolve: F) -> Result<Scene, LoadError>
+    where
+        F: FnMut(&str) -> Option<Mesh>,
+    {
+        let mut scene = Scene::new();
+        for node in &self.nodes {
+            build_node(&mut scene, None, node, &mut resolve)?;
+        }
+        Ok(scene)
+    }
+
+    pub fn capture_properties(&mut self, properties: &Properties) {
+        self.properties = properties
+            .iter()
+            .map(|p| PropertyDescription {
+                name: p.name().to_string(),
+                value: p.get(),
+                line: 0,
+                column: 0,
+            })
+            .collect();
+    }
+
+    // Properties that are stored in the document but unknown to `properties` are ignored. When
+    // a stored value does not have the type of its property nothing is set.
+    pub fn apply_properties(&self, properties: &mut Properties) -> Result<(), LoadError> {
+        for stored in &self.properties {
+            let current = properties.iter().find(|p| p.name() == stored.name.as_str());
+            if let Some(p) = current {
+                let expected = p.get();
+                if value_kind(&expected) != value_kind(&stored.value) {
+                    return Err(LoadError {
+                        line: stored.line,
+                        column: stored.column,
+                        kind: LoadErrorKind::TypeMismatch {
+                            field: stored.name.clone(),
+                            expected: value_kind(&expected),
+                            found: value_kind(&stored.value).to_string(),
+                        },
+                    });
+                }
+            }
+        }
+        for stored in &self.properties {
+            if let Some(p) = properties.iter_mut().find(|p| p.name() == stored.name.as_str()) {
+                p.set(stored.value.clone());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let mut source = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut source))
+            .map_err(|err| LoadError {
+                line: 0,
+                column: 0,
+                kind: LoadErrorKind::Io(err),
+            })?;
+        Self::parse(&source)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        use std::io::Write;
+        File::create(path)?.write_all(self.to_string().as_bytes())
+    }
+
+    pub fn parse(source: &str) -> Result<Self, LoadError> {
+        let mut parser = Parser::new(source);
+        let mut document = SceneDocument::new();
+        while let Some(token) = parser.next_token()? {
+            match token.kind {
+                TokenKind::Word(ref w) if w == "node" => {
+                    document.nodes.push(parser.node()?);
+                }
+                TokenKind::Word(ref w) if w == "property" => {
+                    let (name, line, column) = parser.string("property")?;
+                    let value = parser.value(&name)?;
+                    document.properties.push(PropertyDescription {
+                        name,
+                        value,
+                        line,
+                        column,
+                    });
+                }
+                TokenKind::Word(_) => return Err(unknown_field(&token, "scene")),
+                _ => return Err(token.unexpected("`node` or `property`")),
+            }
+        }
+        Ok(document)
+    }
+}
+
+impl fmt::Display for SceneDocument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        for node in &self.nodes {
+            write_node(&mut out, node, 0)?;
+        }
+        for property in &self.properties {
+            let value = match property.value {
+                Value::Float(v) => v.to_string(),
+                Value::Integer(v) => v.to_string(),
+                Value::Bool(v) => v.to_string(),
+            };
+            writeln!(
+                out,
+                "property {} {} {}",
+                quote(&property.name),
+                value_kind(&property.value),
+                value
+            )?;
+        }
+        f.write_str(&out)
+    }
+}
+
+fn describe_node<F>(scene: &Scene, id: NodeId, reference: &mut F) -> NodeDescription
+where
+    F: FnMut(NodeId, &Node) -> Option<String>,
+{
+    let node = scene.get(id).unwrap();
+    let mesh = node.content().map(|content| match reference(id, node) {
+        Some(name) => MeshSource::Reference {
+            name,
+            line: 0,
+            column: 0,
+        },
+        // Only the mesh's own transform is baked, the node and its ancestors are written as
+        // node transforms
+        None => {
+            let local = content.transform();
+            MeshSource::Inline(content.mesh().transform(&local))
+        }
+    });
+    NodeDescription {
+        name: node.name.clone(),
+        transform: node.transform,
+        visible: node.visible,
+        mesh,
+        light: node.light.clone(),
+        camera: node.camera.clone(),
+        children: node.children()
+            .iter()
+            .map(|&child| describe_node(scene, child, reference))
+            .collect(),
+    }
+}
+
+fn build_node<F>(
+    scene: &mut Scene,
+    parent: Option<NodeId>,
+    description: &NodeDescription,
+    resolve: &mut F,
+) -> Result<(), LoadError>
+where
+    F: FnMut(&str) -> Option<Mesh>,
+{
+    let mut node = Node::new().transform(description.transform);
+    node.name = description.name.clone();
+    node.visible = description.visible;
+    node.light = description.light.clone();
+    node.camera = description.camera.clone();
+    match description.mesh {
+        Some(MeshSource::Inline(ref mesh)) => node.set_content(mesh.clone()),
+        Some(MeshSource::Reference {
+            ref name,
+            line,
+            column,
+        }) => match resolve(name) {
+            Some(mesh) => node.set_content(mesh),
+            None => {
+                return Err(LoadError {
+                    line,
+                    column,
+                    kind: LoadErrorKind::UnresolvedMesh(name.clone()),
+                })
+            }
+        },
+        None => {}
+    }
+    let id = match parent {
+        Some(p) => scene.add_child(p, node).unwrap(),
+        None => scene.add(node),
+    };
+    for child in &description.children {
+        build_node(scene, Some(id), child, resolve)?;
+    }
+    Ok(())
+}
+
+/// Writing
+/// -------
+
+fn value_kind(value: &Value) -> &'static str {
+    match *value {
+        Value::Float(_) => "float",
+        Value::Integer(_) => "int",
+        Value::Bool(_) => "bool",
+    }
+}
+
+// Only with the escapes that `Parser::next_token` reads back.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn indent(out: &mut String, level: usize) {
+    for _ in 0..level {
+        out.push_str("    ");
+    }
+}
+
+fn write_node(out: &mut String, node: &NodeDescription, level: usize) -> fmt::Result {
+    indent(out, level);
+    match node.name {
+        Some(ref name) => writeln!(out, "node {} {{", quote(name))?,
+        None => writeln!(out, "node {{")?,
+    }
+    let inner = level + 1;
+    let t = &node.transform;
+    let q = t.rotation.as_ref().coords;
+    indent(out, inner);
+    writeln!(out, "translation {} {} {}", t.translation.x, t.translation.y, t.translation.z)?;
+    indent(out, inner);
+    writeln!(out, "rotation {} {} {} {}", q.x, q.y, q.z, q.w)?;
+    indent(out, inner);
+    writeln!(out, "scale {} {} {}", t.scale.x, t.scale.y, t.scale.z)?;
+    if !node.visible {
+        indent(out, inner);
+        writeln!(out, "visible false")?;
+    }
+    match node.mesh {
+        Some(MeshSource::Reference { ref name, .. }) => {
+            indent(out, inner);
+            writeln!(out, "mesh {}", quote(name))?;
+        }
+        Some(MeshSource::Inline(ref mesh)) => write_mesh(out, mesh, inner)?,
+        None => {}
+    }
+    if let Some(ref light) = node.light {
+        indent(out, inner);
+        writeln!(out, "light {{")?;
+        indent(out, inner + 1);
+        match light.kind {
+            LightKind::Point { position: p, .. } => {
+                writeln!(out, "point {} {} {}", p.x, p.y, p.z)?
+            }
+            LightKind::Directional { direction: d } => {
+                writeln!(out, "directional {} {} {}", d.x, d.y, d.z)?
+            }
+            LightKind::Spot {
+                position: p,
+                direction: d,
+                inner_angle,
+                outer_angle,
+                ..
+            } => writeln!(
+                out,
+                "spot {} {} {} {} {} {} {} {}",
+                p.x, p.y, p.z, d.x, d.y, d.z, inner_angle, outer_angle
+            )?,
+            LightKind::Ambient => writeln!(out, "ambient")?,
+            LightKind::Hemisphere { up: u, ground: g } => writeln!(
+                out,
+                "hemisphere {} {} {} {} {} {}",
+                u.x, u.y, u.z, g.x, g.y, g.z
+            )?,
+        }
+        match light.kind {
+            LightKind::Point { attenuation, .. } | LightKind::Spot { attenuation, .. } => {
+                indent(out, inner + 1);
+                write_attenuation(out, &attenuation)?;
+            }
+            _ => {}
+        }
+        indent(out, inner + 1);
+        writeln!(out, "color {} {} {}", light.color.x, light.color.y, light.color.z)?;
+        indent(out, inner + 1);
+        writeln!(out, "intensity {}", light.intensity)?;
+        indent(out, inner);
+        writeln!(out, "}}")?;
+    }
+    if let Some(ref camera) = node.camera {
+        indent(out, inner);
+        writeln!(out, "camera {{")?;
+        indent(out, inner + 1);
+        match camera.lens {
+            Lens::Perspective(ref p) => {
+                match p.far {
+                    Some(far) => writeln!(
+                        out,
+                        "perspective {} {} {} {}",
+                        p.fov, p.aspect, p.near, far
+                    )?,
+                    None => writeln!(
+                        out,
+                        "infinite_perspective {} {} {}",
+                        p.fov, p.aspect, p.near
+                    )?,
+                }
+                if p.reversed_z {
+                    indent(out, inner + 1);
+                    writeln!(out, "reversed_z true")?;
+                }
+            }
+            Lens::Orthographic(ref o) => writeln!(
+                out,
+                "orthographic {} {} {} {} {} {}",
+                o.left, o.right, o.bottom, o.top, o.near, o.far
+            )?,
+            Lens::Matrix(ref m) => {
+                write!(out, "projection")?;
+                for r in 0..4 {
+                    for c in 0..4 {
+                        write!(out, " {}", m[(r, c)])?;
+                    }
+                }
+                writeln!(out)?;
+            }
+        }
+        indent(out, inner);
+        writeln!(out, "}}")?;
+    }
+    for child in &node.children {
+        write_node(out, child, inner)?;
+    }
+    indent(out, level);
+    writeln!(out, "}}")
+}
+
+fn write_attenuation(out: &mut String, attenuation: &Attenuation) -> fmt::Result {
+    match *attenuation {
+        Attenuation::Polynomial {
+            constant,
+            linear,
+            quadratic,
+        } => writeln!(out, "attenuation {} {} {}", constant, linear, quadratic),
+        Attenuation::InverseSquare { range } => {
+            writeln!(out, "inverse_square_attenuation {}", range)
+        }
+        Attenuation::Smooth { range } => writeln!(out, "smooth_attenuation {}", range),
+    }
+}
+
+fn write_mesh(out: &mut String, mesh: &Mesh, level: usize) -> fmt::Result {
+    indent(out, level);
+    writeln!(out, "mesh {{")?;
+    for triangle in &mesh.triangles {
+        indent(out, level + 1);
+        writeln!(out, "triangle {{")?;
+        for v in triangle.clone() {
+            indent(out, level + 2);
+            writeln!(
+                out,
+                "vertex {{ position {} {} {} color {} {} {} {} texture {} {} }}",
+                v.position.x,
+                v.position.y,
+                v.position.z,
+                v.color.x,
+                v.color.y,
+                v.color.z,
+                v.color.w,
+                v.texture.x,
+                v.texture.y
+            )?;
+        }
+        indent(out, level + 1);
+        writeln!(out, "}}")?;
+    }
+    indent(out, level);
+    writeln!(out, "}}")
+}
+
+/// Reading
+/// -------
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    Number(f64),
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self.kind {
+            TokenKind::Word(ref w) => format!("`{}`", w),
+            TokenKind::Str(ref s) => format!("string {:?}", s),
+            TokenKind::Number(n) => format!("number {}", n),
+            TokenKind::Open => "`{`".to_string(),
+            TokenKind::Close => "`}`".to_string(),
+        }
+    }
+    fn error(&self, kind: LoadErrorKind) -> LoadError {
+        LoadError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+    fn unexpected(&self, expected: &'static str) -> LoadError {
+        self.error(LoadErrorKind::UnexpectedToken {
+            expected,
+            found: self.describe(),
+        })
+    }
+    fn mismatch(&self, field: &str, expected: &'static str) -> LoadError {
+        self.error(LoadErrorKind::TypeMismatch {
+            field: field.to_string(),
+            expected,
+            found: self.describe(),
+        })
+    }
+}
+
+struct Parser<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+    peeked: Option<Token>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Parser {
+            chars: source.chars().peekable(),
+            line: 1,
+            column: 1,
+            peeked: None,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn end_error(&self) -> LoadError {
+        LoadError {
+            line: self.line,
+            column: self.column,
+            kind: LoadErrorKind::UnexpectedEnd,
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, LoadError> {
+        if let Some(token) = self.peeked.take() {
+            return Ok(Some(token));
+        }
+        loop {
+            match self.chars.peek().cloned() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('#') => while self.chars.peek().map_or(false, |&c| c != '\n') {
+                    self.bump();
+                },
+                _ => break,
+            }
+        }
+        let (line, column) = (self.line, self.column);
+        let kind = match self.bump() {
+            None => return Ok(None),
+            Some('{') => TokenKind::Open,
+            Some('}') => TokenKind::Close,
+            Some('"') => {
+                let mut s = String::new();
+                loop {
+                    match self.bump() {
+                        None => return Err(self.end_error()),
+                        Some('"') => break,
+                        Some('\\') => match self.bump() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some(c) => s.push(c),
+                            None => return Err(self.end_error()),
+                        },
+                        Some(c) => s.push(c),
+                    }
+                }
+                TokenKind::Str(s)
+            }
+            Some(c) => {
+                let mut s = c.to_string();
+                while self.chars
+                    .peek()
+                    .map_or(false, |&c| !c.is_whitespace() && c != '{' && c != '}' && c != '"')
+                {
+                    s.push(self.bump().unwrap());
+                }
+                match s.parse::<f64>() {
+                    Ok(n) => TokenKind::Number(n),
+                    Err(_) => TokenKind::Word(s),
+                }
+            }
+        };
+        Ok(Some(Token { kind, line, column }))
+    }
+
+    fn expect_token(&mut self) -> Result<Token, LoadError> {
+        match self.next_token()? {
+            Some(token) => Ok(token),
+            None => Err(self.end_error()),
+        }
+    }
+
+    fn open(&mut self) -> Result<(), LoadError> {
+        let token = self.expect_token()?;
+        match token.kind {
+            TokenKind::Open => Ok(()),
+            _ => Err(token.unexpected("`{`")),
+        }
+    }
+
+    // Next field name of a block, or `None` when the block is closed.
+    fn field(&mut self) -> Result<Option<Token>, LoadError> {
+        let token = self.expect_token()?;
+        match token.kind {
+            TokenKind::Close => Ok(None),
+            TokenKind::Word(_) => Ok(Some(token)),
+            _ => Err(token.unexpected("a field name or `}`")),
+        }
+    }
+
+    // The string with its line and column.
+    fn string(&mut self, field: &str) -> Result<(String, usize, usize), LoadError> {
+        let token = self.expect_token()?;
+        match token.kind {
+            TokenKind::Str(s) => Ok((s, token.line, token.column)),
+            _ => Err(token.mismatch(field, "a string")),
+        }
+    }
+
+    fn number(&mut self, field: &str) -> Result<f32, LoadError> {
+        let token = self.expect_token()?;
+        match token.kind {
+            TokenKind::Number(n) => Ok(n as f32),
+            _ => Err(token.mismatch(field, "a number")),
+        }
+    }
+
+    fn numbers(&mut self, field: &str, n: usize) -> Result<Vec<f32>, LoadError> {
+        (0..n).map(|_| self.number(field)).collect()
+    }
+
+    fn boolean(&mut self, field: &str) -> Result<bool, LoadError> {
+        let token = self.expect_token()?;
+        match token.kind {
+            TokenKind::Word(ref w) if w == "true" => Ok(true),
+            TokenKind::Word(ref w) if w == "false" => Ok(false),
+            _ => Err(token.mismatch(field, "`true` or `false`")),
+        }
+    }
+
+    fn value(&mut self, field: &str) -> Result<Value, LoadError> {
+        let token = self.expect_token()?;
+        match token.kind {
+            TokenKind::Word(ref w) if w == "float" => Ok(Value::Float(self.number(field)?)),
+            TokenKind::Word(ref w) if w == "bool" => Ok(Value::Bool(self.boolean(field)?)),
+            TokenKind::Word(ref w) if w == "int" => {
+                let token = self.expect_token()?;
+                match token.kind {
+                    TokenKind::Number(n) if n.fract() == 0.0 => Ok(Value::Integer(n as i32)),
+                    _ => Err(token.mismatch(field, "an integer")),
+                }
+            }
+            _ => Err(token.unexpected("`float`, `int` or `bool`")),
+        }
+    }
+
+    fn node(&mut self) -> Result<NodeDescription, LoadError> {
+        let name = match self.expect_token()? {
+            Token {
+                kind: TokenKind::Str(s),
+                ..
+            } => {
+                self.open()?;
+                Some(s)
+            }
+            Token {
+                kind: TokenKind::Open,
+                ..
+            } => None,
+            token => return Err(token.unexpected("a node name or `{`")),
+        };
+        let mut node = NodeDescription {
+            name,
+            transform: Transform::identity(),
+            visible: true,
+            mesh: None,
+            light: None,
+            camera: None,
+            children: Vec::new(),
+        };
+        while let Some(field) = self.field()? {
+            let name = field_name(&field);
+            match name.as_str() {
+                "translation" => {
+                    let v = self.numbers(&name, 3)?;
+                    node.transform.translation = Vector3::new(v[0], v[1], v[2]);
+                }
+                "rotation" => {
+                    let v = self.numbers(&name, 4)?;
+                    node.transform.rotation =
+                        UnitQuaternion::from_quaternion(Quaternion::new(v[3], v[0], v[1], v[2]));
+                }
+                "scale" => {
+                    let v = self.numbers(&name, 3)?;
+                    node.transform.scale = Vector3::new(v[0], v[1], v[2]);
+                }
+                "visible" => node.visible = self.boolean(&name)?,
+                "mesh" => node.mesh = Some(self.mesh()?),
+                "light" => node.light = Some(self.light()?),
+                "camera" => node.camera = Some(self.camera()?),
+                "node" => node.children.push(self.node()?),
+                _ => return Err(unknown_field(&field, "node")),
+            }
+        }
+        Ok(node)
+    }
+
+    fn mesh(&mut self) -> Result<MeshSource, LoadError> {
+        let token = self.expect_token()?;
+        match token.kind {
+            TokenKind::Str(name) => {
+                return Ok(MeshSource::Reference {
+                    name,
+                    line: token.line,
+                    column: token.column,
+                })
+            }
+            TokenKind::Open => {}
+            _ => return Err(token.mismatch("mesh", "a mesh name or a mesh block")),
+        }
+        let mut triangles = Vec::new();
+        while let Some(field) = self.field()? {
+            match field_name(&field).as_str() {
+                "triangle" => triangles.push(self.triangle(&field)?),
+                _ => return Err(unknown_field(&field, "mesh")),
+            }
+        }
+        Ok(MeshSource::Inline(Mesh { triangles }))
+    }
+
+    fn triangle(&mut self, start: &Token) -> Result<Triangle, LoadError> {
+        self.open()?;
+        let mut vertices = Vec::new();
+        while let Some(field) = self.field()? {
+            match field_name(&field).as_str() {
+                "vertex" => vertices.push(self.vertex()?),
+                _ => return Err(unknown_field(&field, "triangle")),
+            }
+        }
+        if vertices.len() != 3 {
+            return Err(start.error(LoadErrorKind::TypeMismatch {
+                field: "triangle".to_string(),
+                expected: "exactly 3 vertices",
+                found: format!("{} vertices", vertices.len()),
+            }));
+        }
+        Ok(Triangle::new(vertices[0], vertices[1], vertices[2]))
+    }
+
+    fn vertex(&mut self) -> Result<Vertex, LoadError> {
+        self.open()?;
+        let mut vertex = Vertex::default();
+        while let Some(field) = self.field()? {
+            let name = field_name(&field);
+            match name.as_str() {
+                "position" => {
+                    let v = self.numbers(&name, 3)?;
+                    vertex.position = Point3::new(v[0], v[1], v[2]);
+                }
+                "color" => {
+                    let v = self.numbers(&name, 4)?;
+                    vertex.color = Vector4::new(v[0], v[1], v[2], v[3]);
+                }
+                "texture" => {
+                    let v = self.numbers(&name, 2)?;
+                    vertex.texture = Point2::new(v[0], v[1]);
+                }
+                _ => return Err(unknown_field(&field, "vertex")),
+            }
+        }
+        Ok(vertex)
+    }
+
+    fn light(&mut self) -> Result<Light, LoadError> {
+        self.open()?;
+        let mut light = Light::point(Point3::origin(), Vector3::new(1.0, 1.0, 1.0), 1.0);
+        let mut attenuation = Attenuation::default();
+        while let Some(field) = self.field()? {
+            let name = field_name(&field);
+            match name.as_str() {
+                "point" => {
+                    let v = self.numbers(&name, 3)?;
+                    light.kind = LightKind::Point {
+                        position: Point3::new(v[0], v[1], v[2]),
+                        attenuation,
+                    };
+                }
+                "directional" => {
+                    let v = self.numbers(&name, 3)?;
+                    light.kind = LightKind::Directional {
+                        direction: Vector3::new(v[0], v[1], v[2]),
+                    };
+                }
+                "spot" => {
+                    let v = self.numbers(&name, 8)?;
+                    light.kind = LightKind::Spot {
+                        position: Point3::new(v[0], v[1], v[2]),
+                        direction: Vector3::new(v[3], v[4], v[5]),
+                        inner_angle: v[6],
+                        outer_angle: v[7],
+                        attenuation,
+                    };
+                }
+                "ambient" => light.kind = LightKind::Ambient,
+                "hemisphere" => {
+                    let v = self.numbers(&name, 6)?;
+                    light.kind = LightKind::Hemisphere {
+                        up: Vector3::new(v[0], v[1], v[2]),
+                        ground: Vector3::new(v[3], v[4], v[5]),
+                    };
+                }
+                "color" => {
+                    let v = self.numbers(&name, 3)?;
+                    light.color = Vector3::new(v[0], v[1], v[2]);
+                }
+                "attenuation" => {
+                    let v = self.numbers(&name, 3)?;
+                    attenuation = Attenuation::polynomial(v[0], v[1], v[2]);
+                }
+                "inverse_square_attenuation" => {
+                    attenuation = Attenuation::inverse_square(self.number(&name)?)
+                }
+                "smooth_attenuation" => attenuation = Attenuation::smooth(self.number(&name)?),
+                // Older files call the intensity strength
+                "intensity" | "strength" => light.intensity = self.number(&name)?,
+                _ => return Err(unknown_field(&field, "light")),
+            }
+        }
+        // The attenuation may come before or after the kind of light
+        Ok(light.attenuation(attenuation))
+    }
+
+    fn camera(&mut self) -> Result<Camera, LoadError> {
+        self.open()?;
+        let mut camera = Camera::perspective();
+        let mut reversed_z = false;
+        while let Some(field) = self.field()? {
+            let name = field_name(&field);
+            match name.as_str() {
+                "perspective" => {
+                    let v = self.numbers(&name, 4)?;
+                    camera.lens = Perspective::new()
+                        .fov(v[0])
+                        .aspect(v[1])
+                        .near(v[2])
+                        .far(v[3])
+                        .into();
+                }
+                "infinite_perspective" => {
+                    let v = self.numbers(&name, 3)?;
+                    camera.lens = Perspective::new()
+                        .fov(v[0])
+                        .aspect(v[1])
+                        .near(v[2])
+                        .infinite()
+                        .into();
+                }
+                "orthographic" => {
+                    let v = self.numbers(&name, 6)?;
+                    camera.lens = Orthographic::extents(v[0], v[1], v[2], v[3])
+                        .near(v[4])
+                        .far(v[5])
+                        .into();
+                }
+                "projection" => {
+                    let v = self.numbers(&name, 16)?;
+                    camera.lens = Lens::Matrix(Matrix4::from_fn(|r, c| v[r * 4 + c]));
+                }
+                "reversed_z" => reversed_z = self.boolean(&name)?,
+                _ => return Err(unknown_field(&field, "camera")),
+            }
+        }
+        if let Lens::Perspective(ref mut p) = camera.lens {
+            p.reversed_z = reversed_z;
+        }
+        Ok(camera)
+    }
+}
+
+fn field_name(token: &Token) -> String {
+    match token.kind {
+        TokenKind::Word(ref w) => w.clone(),
+        _ => String::new(),
+    }
+}
+
+fn unknown_field(token: &Token, block: &'static str) -> LoadError {
+    token.error(LoadErrorKind::UnknownField {
+        block,
+        field: field_name(token),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alga::linear::Transformation;
+    use mesh_renderer::IntoMesh;
+
+    fn triangle() -> Mesh {
+        Mesh {
+            triangles: vec![Triangle::default()],
+        }
+    }
+
+    #[test]
+    fn lights_and_cameras_survive_a_round_trip() {
+        let mut scene = Scene::new();
+        let lamp = Light::spot(
+            Point3::new(1.0, 2.0, 3.0),
+            Vector3::new(0.0, -1.0, 0.0),
+            Vector3::new(1.0, 0.5, 0.25),
+            2.0,
+        ).attenuation(Attenuation::smooth(8.0));
+        let eye = Camera::new(Perspective::new().fov(0.8).near(0.5).far(50.0));
+        let root = scene.add(Node::new().named("root\r\"\\").light(lamp.clone()));
+        scene
+            .add_child(root, Node::new().named("eye").camera(eye.clone()))
+            .unwrap();
+        scene
+            .add_child(root, Node::new().named("hull").mesh(triangle()))
+            .unwrap();
+
+        let text = SceneDocument::from_scene(&scene, |_, _| Some("hull".to_string())).to_string();
+        let loaded = SceneDocument::parse(&text)
+            .unwrap()
+            .to_scene(|name| if name == "hull" { Some(triangle()) } else { None })
+            .unwrap();
+
+        let root = loaded.find("root\r\"\\").unwrap();
+        assert_eq!(loaded.get(root).unwrap().light, Some(lamp));
+        let eye_id = loaded.find("eye").unwrap();
+        assert_eq!(loaded.get(eye_id).unwrap().camera, Some(eye));
+        assert!(loaded.get(loaded.find("hull").unwrap()).unwrap().content().is_some());
+    }
+
+    struct Raised;
+
+    impl IntoMesh for Raised {
+        fn transform(&self) -> Matrix4<f32> {
+            Matrix4::new_translation(&Vector3::new(0.0, 1.0, 0.0))
+        }
+        fn mesh(&self) -> Mesh {
+            triangle()
+        }
+    }
+
+    #[test]
+    fn inline_meshes_keep_their_place_under_a_moved_parent() {
+        let mut scene = Scene::new();
+        let moved = Transform::identity().translation(Vector3::new(5.0, 0.0, 0.0));
+        let parent = scene.add(Node::new().named("parent").transform(moved));
+        let child = scene
+            .add_child(parent, Node::new().named("child").mesh(Raised))
+            .unwrap();
+        scene.refresh();
+        let world = scene.world_transform(child).unwrap();
+
+        let text = SceneDocument::from_scene(&scene, |_, _| None).to_string();
+        let loaded = SceneDocument::parse(&text)
+            .unwrap()
+            .to_scene(|_| None)
+            .unwrap();
+        loaded.refresh();
+
+        let child = loaded.find("child").unwrap();
+        assert_eq!(loaded.world_transform(child), Some(world));
+        // Where the first vertex ends up in the world
+        let placed = |transform: Matrix4<f32>, mesh: Mesh| {
+            transform.transform_point(&mesh.triangles[0].v1.position)
+        };
+        let content = loaded.get(child).unwrap().content().unwrap();
+        assert_eq!(
+            placed(world * content.transform(), content.mesh()),
+            placed(world * Raised.transform(), Raised.mesh())
+        );
+    }
+
+    #[test]
+    fn unresolved_meshes_report_their_position() {
+        let document = SceneDocument::parse("node {\n    mesh \"missing\"\n}").unwrap();
+        let err = document.to_scene(|_| None).err().unwrap();
+        assert_eq!((err.line, err.column), (2, 10));
+        match err.kind {
+            LoadErrorKind::UnresolvedMesh(ref name) => assert_eq!(name, "missing"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn properties_of_another_type_are_not_applied() {
+        let document =
+            SceneDocument::parse("property \"scale\" float 2\nproperty \"speed\" int 3").unwrap();
+        let mut scale = 1.0f32;
+        let mut speed = 0.5f32;
+        let err = {
+            let mut properties = Properties::new()
+                .add("scale", &mut scale)
+                .add("speed", &mut speed);
+            document.apply_properties(&mut properties).err().unwrap()
+        };
+        assert_eq!((err.line, err.column), (2, 10));
+        match err.kind {
+            LoadErrorKind::TypeMismatch {
+                ref field,
+                expected,
+                ref found,
+            } => assert_eq!((field.as_str(), expected, found.as_str()), ("speed", "float", "int")),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert_eq!((scale, speed), (1.0, 0.5));
+    }
+
+    fn parse_error(source: &str) -> LoadError {
+        SceneDocument::parse(source).err().unwrap()
+    }
+
+    #[test]
+    fn unexpected_end() {
+        let err = parse_error("node \"ship\" {\n    translation 1 2");
+        assert_eq!((err.line, err.column), (2, 20));
+        match err.kind {
+            LoadErrorKind::UnexpectedEnd => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(SceneDocument::parse("node \"unclosed").is_err());
+    }
+
+    #[test]
+    fn unexpected_token() {
+        let err = parse_error("# comment\n  { }");
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.to_string(), "2:3: expected `node` or `property`, found `{`");
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let err = parse_error("node {\n    light {\n        glow 1\n    }\n}");
+        assert_eq!(err.to_string(), "3:9: unknown field `glow` in light");
+        let err = parse_error("scene {}");
+        assert_eq!(err.to_string(), "1:1: unknown field `scene` in scene");
+    }
+
+    #[test]
+    fn values_of_the_wrong_type() {
+        let err = parse_error("node {\n    translation 1 2 true\n}");
+        assert_eq!(err.to_string(), "2:21: `translation` expects a number, found `true`");
+        let err = parse_error("property \"lives\" int 2.5");
+        assert_eq!(err.to_string(), "1:22: `lives` expects an integer, found number 2.5");
+        let err = parse_error("node { mesh { triangle { vertex { } } } }");
+        assert_eq!(
+            err.to_string(),
+            "1:15: `triangle` expects exactly 3 vertices, found 1 vertices"
+        );
+    }
+}
@@ -0,0 +1,614 @@
+use alga::linear::Transformation;
+use mursten::{Backend, Data, RenderChain, UpdateChain};
+use nalgebra::*;
+use std::collections::HashMap;
+use std::f32;
+
+use super::camera::{self, Camera, Viewport};
+use super::geometry::{Mesh, Triangle};
+use super::image_output;
+use super::input::{self, ResizeEvent};
+use super::instancing::{self, Instance};
+use super::light::{self, Light};
+use super::material::Material;
+use super::mesh_renderer;
+
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn get(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+    pub fn set(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let i = (y * self.width + x) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&rgba);
+    }
+    pub fn clear(&mut self, rgba: [u8; 4]) {
+        for pixel in self.pixels.chunks_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+    }
+    // Rows from top to bottom, four bytes per pixel.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+pub type Sampler = Box<Fn(Point2<f32>) -> Vector4<f32>>;
+
+// Renders everything it was given through the backend traits into an in-memory framebuffer.
+// Meshes are queued by `queue_render` and drawn with the camera and light that were set last
+// when `render_frame` is called. Meshes queued after a `set_viewport` are drawn with the camera
+// of that viewport instead, inside its area.
+//
+// As a `Backend` it runs the updaters and renderers and then draws the frame, until `quit` is
+// called or `max_frames` frames were drawn, so applications can run without a GPU.
+pub struct SoftwareRenderer {
+    framebuffer: Framebuffer,
+    depth: Vec<f32>,
+    queue: Vec<Command>,
+    textures: HashMap<String, Sampler>,
+    view: Matrix4<f32>,
+    projection: Matrix4<f32>,
+    reversed_z: bool,
+    // Pixel area being drawn, as `(x, y, width, height)`.
+    area: (usize, usize, usize, usize),
+    resize_events: Vec<ResizeEvent>,
+    lights: Vec<Light>,
+    // Added to the ambient lights, when there are lights at all.
+    pub ambient: f32,
+    pub clear_color: Vector4<f32>,
+    // Used for the meshes whose material names no texture.
+    pub texture: Option<Sampler>,
+    // Something was queued, or a frame started, since the framebuffer was last drawn.
+    pending: bool,
+    quit_requested: bool,
+    max_frames: Option<usize>,
+    frames_run: usize,
+}
+
+impl SoftwareRenderer {
+    pub fn new(width: usize, height: usize) -> Self {
+        SoftwareRenderer {
+            framebuffer: Framebuffer::new(width, height),
+            depth: vec![f32::INFINITY; width * height],
+            queue: Vec::new(),
+            textures: HashMap::new(),
+            view: Matrix4::identity(),
+            projection: Camera::perspective().projection(),
+            reversed_z: false,
+            area: (0, 0, width, height),
+            resize_events: vec![ResizeEvent::new(width, height)],
+            lights: Vec::new(),
+            ambient: 0.2,
+            clear_color: Vector4::new(0.0, 0.0, 0.0, 1.0),
+            texture: None,
+            pending: true,
+            quit_requested: false,
+            max_frames: None,
+            frames_run: 0,
+        }
+    }
+
+    // Stops `run` after this many frames, unless `quit` is called earlier.
+    pub fn max_frames(self, max_frames: usize) -> Self {
+        Self {
+            max_frames: Some(max_frames),
+            ..self
+        }
+    }
+
+    pub fn frames_run(&self) -> usize {
+        self.frames_run
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    // Makes the texture available to materials that reference it by `name`.
+    pub fn add_texture(&mut self, name: &str, sampler: Sampler) {
+        self.textures.insert(name.to_string(), sampler);
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.framebuffer = Framebuffer::new(width, height);
+        self.depth = vec![f32::INFINITY; width * height];
+        self.resize_events.push(ResizeEvent::new(width, height));
+    }
+
+    // Clears the framebuffer, draws and empties the queue.
+    pub fn render_frame(&mut self) -> &Framebuffer {
+        let clear = to_rgba8(&self.clear_color);
+        self.framebuffer.clear(clear);
+        for d in self.depth.iter_mut() {
+            *d = f32::INFINITY;
+        }
+        let camera = (self.view, self.projection, self.reversed_z);
+        self.area = (0, 0, self.framebuffer.width, self.framebuffer.height);
+        let mut eye = eye_position(&self.view);
+        let queue: Vec<Command> = self.queue.drain(..).collect();
+        for command in queue {
+            match command {
+                Command::Draw(transform, mesh, material) => for triangle in &mesh.triangles {
+                    self.draw_triangle(&transform, triangle, &material, &eye);
+                },
+                Command::Viewport(view, projection, reversed_z, viewport) => {
+                    self.view = view;
+                    self.projection = projection;
+                    self.reversed_z = reversed_z;
+                    eye = eye_position(&view);
+                    self.area = viewport.pixels(self.framebuffer.width, self.framebuffer.height);
+                    self.clear_area(clear);
+                }
+            }
+        }
+        // Viewports only last for the frame they were set in
+        let (view, projection, reversed_z) = camera;
+        self.view = view;
+        self.projection = projection;
+        self.reversed_z = reversed_z;
+        self.pending = false;
+        &self.framebuffer
+    }
+
+    fn clear_area(&mut self, rgba: [u8; 4]) {
+        let (x0, y0, width, height) = self.area;
+        for y in y0..y0 + height {
+            for x in x0..x0 + width {
+                self.framebuffer.set(x, y, rgba);
+                self.depth[y * self.framebuffer.width + x] = f32::INFINITY;
+            }
+        }
+    }
+
+    fn draw_triangle(
+        &mut self,
+        model: &Matrix4<f32>,
+        triangle: &Triangle,
+        material: &Material,
+        eye: &Point3<f32>,
+    ) {
+        let world: Vec<Point3<f32>> = (*triangle)
+            .into_iter()
+            .map(|v| model.transform_point(&v.position))
+            .collect();
+        let view_projection = self.projection * self.view;
+        let clip: Vec<Vector4<f32>> = world
+            .iter()
+            .map(|p| view_projection * p.to_homogeneous())
+            .collect();
+        let mut normal = (world[1] - world[0]).cross(&(world[2] - world[0]));
+        // Counter clockwise triangles on screen are front facing, as in `SvgRenderer`
+        if projected_area(&clip) < 0.0 {
+            if !material.double_sided {
+                return;
+            }
+            normal = -normal;
+        }
+
+        let vertices: Vec<ClipVertex> = (*triangle)
+            .into_iter()
+            .zip(world.iter().zip(clip))
+            .map(|(v, (p, position))| ClipVertex {
+                position,
+                color: self.shade(p, &normal, eye, &material.surface_color(&v.color), material),
+                texture: v.texture.coords,
+            })
+            .collect();
+
+        let clipped = clip_near(vertices);
+        for i in 1..clipped.len().saturating_sub(1) {
+            self.rasterize(&clipped[0], &clipped[i], &clipped[i + 1], &material.texture);
+        }
+    }
+
+    // Per vertex lighting, see `light::contribution`. Double sided triangles are lit on the side
+    // that faces the eye. Without lights everything is drawn unlit.
+    fn shade(
+        &self,
+        p: &Point3<f32>,
+        normal: &Vector3<f32>,
+        eye: &Point3<f32>,
+        color: &Vector4<f32>,
+        material: &Material,
+    ) -> Vector4<f32> {
+        let emissive = Vector4::new(material.emissive.x, material.emissive.y, material.emissive.z, 0.0);
+        if self.lights.is_empty() || material.unlit {
+            return color + emissive;
+        }
+        let surface = light::Surface::new(*p, *normal, *eye)
+            .shininess(material.shininess)
+            .double_sided(material.double_sided);
+        let contribution = light::contribution(&self.lights, &surface);
+        let diffuse = contribution.diffuse + Vector3::repeat(self.ambient);
+        let lit = Vector3::new(color.x * diffuse.x, color.y * diffuse.y, color.z * diffuse.z)
+            + material.specular.component_mul(&contribution.specular);
+        Vector4::new(lit.x, lit.y, lit.z, color.w) + emissive
+    }
+
+    fn rasterize(
+        &mut self,
+        a: &ClipVertex,
+        b: &ClipVertex,
+        c: &ClipVertex,
+        texture: &Option<String>,
+    ) {
+        let (x0, y0, width, height) = self.area;
+        let (left, top) = (x0 as f32, y0 as f32);
+        let (right, bottom) = ((x0 + width) as f32, (y0 + height) as f32);
+        let screen = |v: &ClipVertex| {
+            let w = v.position.w;
+            Vector3::new(
+                left + (v.position.x / w + 1.0) / 2.0 * width as f32,
+                top + (1.0 - v.position.y / w) / 2.0 * height as f32,
+                v.position.z / w,
+            )
+        };
+        let (sa, sb, sc) = (screen(a), screen(b), screen(c));
+        let area = edge(&sa, &sb, &sc);
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+
+        let min_x = sa.x.min(sb.x).min(sc.x).max(left).floor() as usize;
+        let min_y = sa.y.min(sb.y).min(sc.y).max(top).floor() as usize;
+        let max_x = sa.x.max(sb.x).max(sc.x).ceil().min(right) as usize;
+        let max_y = sa.y.max(sb.y).max(sc.y).ceil().min(bottom) as usize;
+
+        let (iwa, iwb, iwc) = (1.0 / a.position.w, 1.0 / b.position.w, 1.0 / c.position.w);
+        let sampler = match *texture {
+            Some(ref name) => self.textures.get(name),
+            None => self.texture.as_ref(),
+        };
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = Vector3::new(x as f32 + 0.5, y as f32 + 0.5, 0.0);
+                let wa = edge(&sb, &sc, &p) / area;
+                let wb = edge(&sc, &sa, &p) / area;
+                let wc = edge(&sa, &sb, &p) / area;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+
+                let depth = wa * sa.z + wb * sb.z + wc * sc.z;
+                let index = y * self.framebuffer.width + x;
+                if depth < -1.0 || depth > 1.0 {
+                    continue;
+                }
+                // The depth buffer always keeps smaller values for closer fragments
+                let depth = if self.reversed_z { -depth } else { depth };
+                if depth >= self.depth[index] {
+                    continue;
+                }
+
+                // Attributes are interpolated over 1/w to be perspective correct
+                let (pa, pb, pc) = (wa * iwa, wb * iwb, wc * iwc);
+                let inv = 1.0 / (pa + pb + pc);
+                let mut color = (a.color * pa + b.color * pb + c.color * pc) * inv;
+                if let Some(texture) = sampler {
+                    let uv = (a.texture * pa + b.texture * pb + c.texture * pc) * inv;
+                    color = color.component_mul(&texture(Point2::new(uv.x, uv.y)));
+                }
+                if color.w <= 0.0 {
+                    continue;
+                }
+
+                let destination = self.framebuffer.get(x, y);
+                let rgba = blend(&color, &destination);
+                self.framebuffer.set(x, y, rgba);
+                self.depth[index] = depth;
+            }
+        }
+    }
+}
+
+enum Command {
+    Draw(Matrix4<f32>, Mesh, Material),
+    // View, projection and reversed depth of the camera, and where it draws.
+    Viewport(Matrix4<f32>, Matrix4<f32>, bool, Viewport),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClipVertex {
+    position: Vector4<f32>,
+    color: Vector4<f32>,
+    texture: Vector2<f32>,
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            position: self.position + (other.position - self.position) * t,
+            color: self.color + (other.color - self.color) * t,
+            texture: self.texture + (other.texture - self.texture) * t,
+        }
+    }
+}
+
+// Sutherland-Hodgman against the near plane, `z >= -w`. The other planes are dealt with by
+// limiting the rasterization to the framebuffer and by the depth range check.
+fn clip_near(vertices: Vec<ClipVertex>) -> Vec<ClipVertex> {
+    let distance = |v: &ClipVertex| v.position.z + v.position.w;
+    let mut result = Vec::with_capacity(4);
+    for i in 0..vertices.len() {
+        let current = &vertices[i];
+        let next = &vertices[(i + 1) % vertices.len()];
+        let (dc, dn) = (distance(current), distance(next));
+        if dc >= 0.0 {
+            result.push(*current);
+        }
+        if (dc >= 0.0) != (dn >= 0.0) {
+            result.push(current.lerp(next, dc / (dc - dn)));
+        }
+    }
+    result
+}
+
+fn eye_position(view: &Matrix4<f32>) -> Point3<f32> {
+    view.try_inverse()
+        .map_or(Point3::origin(), |m| m.transform_point(&Point3::origin()))
+}
+
+// Signed area of the triangle in normalized device coordinates, positive when counter clockwise,
+// times the `w` of its three vertices. Taken over `x`, `y` and `w`, so the sign is right even for
+// vertices behind the camera, before clipping.
+fn projected_area(clip: &[Vector4<f32>]) -> f32 {
+    let xyw = |v: &Vector4<f32>| Vector3::new(v.x, v.y, v.w);
+    xyw(&clip[0]).dot(&xyw(&clip[1]).cross(&xyw(&clip[2])))
+}
+
+fn edge(a: &Vector3<f32>, b: &Vector3<f32>, p: &Vector3<f32>) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+fn to_rgba8(color: &Vector4<f32>) -> [u8; 4] {
+    let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    [
+        channel(color.x),
+        channel(color.y),
+        channel(color.z),
+        channel(color.w),
+    ]
+}
+
+fn blend(source: &Vector4<f32>, destination: &[u8; 4]) -> [u8; 4] {
+    let alpha = source.w.max(0.0).min(1.0);
+    let d = Vector4::new(
+        destination[0] as f32 / 255.0,
+        destination[1] as f32 / 255.0,
+        destination[2] as f32 / 255.0,
+        destination[3] as f32 / 255.0,
+    );
+    let out = Vector4::new(
+        source.x * alpha + d.x * (1.0 - alpha),
+        source.y * alpha + d.y * (1.0 - alpha),
+        source.z * alpha + d.z * (1.0 - alpha),
+        alpha + d.w * (1.0 - alpha),
+    );
+    to_rgba8(&out)
+}
+
+impl mesh_renderer::backend::RenderMesh for SoftwareRenderer {
+    fn queue_render(&mut self, transform: Matrix4<f32>, mesh: Mesh) {
+        self.queue.push(Command::Draw(transform, mesh, Material::default()));
+        self.pending = true;
+    }
+}
+
+impl mesh_renderer::backend::RenderMaterialMesh for SoftwareRenderer {
+    fn queue_render_material(&mut self, transform: Matrix4<f32>, mesh: Mesh, material: Material) {
+        self.queue.push(Command::Draw(transform, mesh, material));
+        self.pending = true;
+    }
+}
+
+impl instancing::backend::RenderInstances for SoftwareRenderer {
+    fn queue_render_instances(&mut self, mesh: Mesh, instances: Vec<Instance>) {
+        instancing::expand(self, mesh, instances);
+    }
+}
+
+impl camera::backend::SetCamera for SoftwareRenderer {
+    fn set_camera(&mut self, transform: Matrix4<f32>, camera: &Camera) {
+        self.view = transform;
+        self.projection = camera.projection();
+        self.reversed_z = camera.reversed_z();
+    }
+}
+
+impl camera::backend::SetViewport for SoftwareRenderer {
+    fn set_viewport(&mut self, transform: Matrix4<f32>, camera: &Camera, viewport: Viewport) {
+        self.queue.push(Command::Viewport(
+            transform,
+            camera.projection(),
+            camera.reversed_z(),
+            viewport,
+        ));
+        self.pending = true;
+    }
+}
+
+impl input::backend::ResizeEventSource for SoftwareRenderer {
+    fn drain_events(&mut self) -> Vec<ResizeEvent> {
+        self.resize_events.drain(..).collect()
+    }
+}
+
+impl light::backend::SetLights for SoftwareRenderer {
+    fn set_lights(&mut self, lights: Vec<Light>) {
+        self.lights = lights;
+    }
+}
+
+// Draws what was queued since the last frame, so a `FrameRecorder` in the render chain records
+// the frame that `run` would draw after it.
+impl image_output::backend::FrameSource for SoftwareRenderer {
+    fn frame(&mut self) -> &Framebuffer {
+        if self.pending {
+            self.render_frame();
+        }
+        &self.framebuffer
+    }
+}
+
+impl<D> Backend<D> for SoftwareRenderer
+where
+    D: Data,
+{
+    fn run(
+        mut self,
+        mut render_chain: RenderChain<Self, D>,
+        mut update_chain: UpdateChain<Self, D>,
+        mut data: D,
+    ) -> D {
+        while !self.quit_requested && self.max_frames.map_or(true, |max| self.frames_run < max) {
+            self.pending = true;
+            update_chain.update(&mut self, &mut data);
+            render_chain.render(&mut self, &data);
+            if self.pending {
+                self.render_frame();
+            }
+            self.frames_run += 1;
+        }
+        data
+    }
+
+    fn quit(&mut self) {
+        self.quit_requested = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::backend::SetCamera;
+    use camera::Orthographic;
+    use geometry::Vertex;
+    use image_output::backend::FrameSource;
+    use camera::{CameraUpdater, GetCamera};
+    use light::{GetLights, LightUpdater};
+    use mesh_renderer::backend::RenderMaterialMesh;
+    use mesh_renderer::{GetMeshes, IntoMesh, MeshRenderer};
+    use mursten::{Renderer, Updater};
+    use std::vec;
+
+    struct Stage {
+        view: Matrix4<f32>,
+        camera: Camera,
+        lights: Vec<Light>,
+        meshes: Vec<Mesh>,
+    }
+
+    impl Data for Stage {}
+
+    impl GetCamera for Stage {
+        fn get_camera<'a>(&'a self) -> (Matrix4<f32>, &'a Camera) {
+            (self.view, &self.camera)
+        }
+    }
+
+    impl GetLights for Stage {
+        fn get_lights(&self) -> Vec<Light> {
+            self.lights.clone()
+        }
+    }
+
+    impl GetMeshes for Stage {
+        fn mesh_iter<'a>(&'a self) -> vec::IntoIter<&IntoMesh> {
+            let meshes: Vec<&IntoMesh> = self.meshes.iter().map(|m| m as &IntoMesh).collect();
+            meshes.into_iter()
+        }
+    }
+
+    fn red_pixels(renderer: &mut SoftwareRenderer) -> usize {
+        renderer
+            .render_frame()
+            .as_bytes()
+            .chunks(4)
+            .filter(|p| p[0] > 0)
+            .count()
+    }
+
+    #[test]
+    fn orthographic_facing_follows_the_screen_winding() {
+        let mut renderer = SoftwareRenderer::new(40, 40);
+        let lens = Orthographic::extents(-20.0, 20.0, -20.0, 20.0).near(0.1).far(100.0);
+        renderer.set_camera(Matrix4::identity(), &Camera::new(lens));
+        let single_sided = Material::new().unlit(true).double_sided(false);
+        let red = |x, y, z| {
+            Vertex::at(Point3::new(x, y, z)).color(Vector4::new(1.0, 0.0, 0.0, 1.0))
+        };
+        // Tilted towards the view axis and far off to the left, so the direction to the eye
+        // points at its back while the view direction sees its front
+        let (a, b, c) = (red(-18.0, -3.0, -8.0), red(-12.0, -3.0, -2.0), red(-15.0, 3.0, -5.0));
+
+        let front = Mesh {
+            triangles: vec![Triangle::new(a, b, c)],
+        };
+        renderer.queue_render_material(Matrix4::identity(), front, single_sided.clone());
+        assert!(red_pixels(&mut renderer) > 0);
+
+        let back = Mesh {
+            triangles: vec![Triangle::new(a, c, b)],
+        };
+        renderer.queue_render_material(Matrix4::identity(), back, single_sided);
+        assert_eq!(red_pixels(&mut renderer), 0);
+    }
+
+    #[test]
+    fn draws_what_the_updaters_and_the_mesh_renderer_send() {
+        let white = |x, y| Vertex::at(Point3::new(x, y, 0.0));
+        let triangle = Triangle::new(white(-1.0, -1.0), white(1.0, -1.0), white(0.0, 1.0));
+        let mut stage = Stage {
+            view: Matrix4::new_translation(&Vector3::new(0.0, 0.0, -3.0)),
+            camera: Camera::new(Orthographic::extents(-2.0, 2.0, -2.0, 2.0).near(0.1).far(100.0)),
+            lights: vec![Light::directional(-Vector3::z(), Vector3::repeat(1.0), 0.5)],
+            meshes: vec![Mesh {
+                triangles: vec![triangle],
+            }],
+        };
+        let mut draw = |stage: &mut Stage| {
+            let mut renderer = SoftwareRenderer::new(20, 20);
+            CameraUpdater::new().update(&mut renderer, stage);
+            LightUpdater::new().update(&mut renderer, stage);
+            MeshRenderer::new().render(&mut renderer, stage);
+            let frame = renderer.frame().as_bytes().to_vec();
+            let pixel = |x: usize, y: usize| frame[(y * 20 + x) * 4];
+            (pixel(10, 10), pixel(0, 0))
+        };
+
+        let (center, corner) = draw(&mut stage);
+        assert_eq!(corner, 0);
+        assert!(center > 0 && center < 255, "lit by half the light and the ambient term");
+
+        stage.lights = vec![Light::directional(-Vector3::z(), Vector3::repeat(1.0), 0.1)];
+        let (dimmer, _) = draw(&mut stage);
+        assert!(dimmer < center);
+    }
+}
@@ -0,0 +1,339 @@
+use mursten::{Backend, Data, RenderChain, UpdateChain};
+use nalgebra::*;
+use std::cmp::Ordering;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::camera::{self, Camera};
+use super::geometry::Mesh;
+use super::mesh_renderer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SvgStyle {
+    // Polygons filled with the average color of their vertices.
+    Filled,
+    // Outlines only. Polygons are painted with the background color so that edges hidden behind
+    // closer polygons are covered.
+    Wireframe {
+        stroke: Vector4<f32>,
+        stroke_width: f32,
+        background: Vector4<f32>,
+    },
+}
+
+// Collects what is queued through `RenderMesh`, projects it with the camera given to
+// `SetCamera` and writes it as SVG polygons. Depth is resolved by drawing the polygons from the
+// farthest to the closest one, so intersecting polygons may not come out right.
+//
+// As a `Backend` it runs the updaters and renderers and writes each frame to the `output`
+// directory, until `quit` is called, `max_frames` frames were written or a file cannot be
+// written.
+pub struct SvgRenderer {
+    width: f32,
+    height: f32,
+    queue: Vec<(Matrix4<f32>, Mesh)>,
+    view: Matrix4<f32>,
+    projection: Matrix4<f32>,
+    reversed_z: bool,
+    pub style: SvgStyle,
+    pub cull_back_faces: bool,
+    output: Option<PathBuf>,
+    quit_requested: bool,
+    max_frames: Option<usize>,
+}
+
+struct Polygon {
+    points: Vec<Point2<f32>>,
+    depth: f32,
+    color: Vector4<f32>,
+}
+
+impl SvgRenderer {
+    pub fn new(width: f32, height: f32) -> Self {
+        SvgRenderer {
+            width,
+            height,
+            queue: Vec::new(),
+            view: Matrix4::identity(),
+            projection: Camera::perspective().projection(),
+            reversed_z: false,
+            style: SvgStyle::Filled,
+            cull_back_faces: true,
+            output: None,
+            quit_requested: false,
+            max_frames: None,
+        }
+    }
+
+    pub fn style(self, style: SvgStyle) -> Self {
+        Self { style, ..self }
+    }
+
+    pub fn cull_back_faces(self, cull_back_faces: bool) -> Self {
+        Self {
+            cull_back_faces,
+            ..self
+        }
+    }
+
+    // Directory `run` writes the frames to, as `frame_00001.svg` and so on. Without it the
+    // frames are drawn and thrown away.
+    pub fn output<P: AsRef<Path>>(self, directory: P) -> Self {
+        Self {
+            output: Some(directory.as_ref().to_path_buf()),
+            ..self
+        }
+    }
+
+    // Stops `run` after this many frames, unless `quit` is called earlier.
+    pub fn max_frames(self, max_frames: usize) -> Self {
+        Self {
+            max_frames: Some(max_frames),
+            ..self
+        }
+    }
+
+    // Produces the document for everything queued so far and empties the queue.
+    pub fn to_svg(&mut self) -> String {
+        let mut polygons = self.project();
+        polygons.sort_by(|a, b| b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal));
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+            w = self.width,
+            h = self.height
+        ).unwrap();
+        if let SvgStyle::Wireframe { background, .. } = self.style {
+            writeln!(
+                svg,
+                "  <rect width=\"100%\" height=\"100%\" {}/>",
+                fill(&background)
+            ).unwrap();
+        }
+        for polygon in polygons {
+            let points: Vec<String> = polygon
+                .points
+                .iter()
+                .map(|p| format!("{:.3},{:.3}", p.x, p.y))
+                .collect();
+            let paint = match self.style {
+                SvgStyle::Filled => fill(&polygon.color),
+                SvgStyle::Wireframe {
+                    stroke,
+                    stroke_width,
+                    background,
+                } => format!(
+                    "{} stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\" stroke-linejoin=\"round\"",
+                    fill(&background),
+                    rgb(&stroke),
+                    stroke.w,
+                    stroke_width
+                ),
+            };
+            writeln!(svg, "  <polygon points=\"{}\" {}/>", points.join(" "), paint).unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let svg = self.to_svg();
+        File::create(path)?.write_all(svg.as_bytes())
+    }
+
+    fn save_frame(&mut self, frame: usize) -> io::Result<()> {
+        let directory = match self.output {
+            Some(ref directory) => directory.clone(),
+            None => {
+                self.queue.clear();
+                return Ok(());
+            }
+        };
+        fs::create_dir_all(&directory)?;
+        self.save(directory.join(format!("frame_{:05}.svg", frame)))
+    }
+
+    fn project(&mut self) -> Vec<Polygon> {
+        let view_projection = self.projection * self.view;
+        let (width, height) = (self.width, self.height);
+        let depth_sign = if self.reversed_z { -1.0 } else { 1.0 };
+        let mut polygons = Vec::new();
+
+        for (transform, mesh) in self.queue.drain(..) {
+            let mvp = view_projection * transform;
+            for triangle in &mesh.triangles {
+                let clip: Vec<Vector4<f32>> = (*triangle)
+                    .into_iter()
+                    .map(|v| mvp * v.position.to_homogeneous())
+                    .collect();
+                let clipped = clip_near(&clip);
+                if clipped.len() < 3 {
+                    continue;
+                }
+                let ndc: Vec<Point3<f32>> = clipped
+                    .iter()
+                    .map(|c| Point3::new(c.x / c.w, c.y / c.w, c.z / c.w))
+                    .collect();
+
+                // Counter clockwise polygons in normalized device coordinates are front facing
+                let area: f32 = (0..ndc.len())
+                    .map(|i| {
+                        let (a, b) = (ndc[i], ndc[(i + 1) % ndc.len()]);
+                        a.x * b.y - b.x * a.y
+                    })
+                    .sum();
+                if self.cull_back_faces && area <= 0.0 {
+                    continue;
+                }
+
+                let color = (triangle.v1.color + triangle.v2.color + triangle.v3.color) / 3.0;
+                polygons.push(Polygon {
+                    points: ndc.iter()
+                        .map(|p| {
+                            Point2::new((p.x + 1.0) / 2.0 * width, (1.0 - p.y) / 2.0 * height)
+                        })
+                        .collect(),
+                    depth: depth_sign * ndc.iter().map(|p| p.z).sum::<f32>() / ndc.len() as f32,
+                    color,
+                });
+            }
+        }
+        polygons
+    }
+}
+
+// Keeps the part of the polygon in front of the near plane, `z >= -w`.
+fn clip_near(vertices: &[Vector4<f32>]) -> Vec<Vector4<f32>> {
+    let distance = |v: &Vector4<f32>| v.z + v.w;
+    let mut result = Vec::with_capacity(4);
+    for i in 0..vertices.len() {
+        let (current, next) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+        let (dc, dn) = (distance(&current), distance(&next));
+        if dc >= 0.0 {
+            result.push(current);
+        }
+        if (dc >= 0.0) != (dn >= 0.0) {
+            result.push(current + (next - current) * (dc / (dc - dn)));
+        }
+    }
+    result
+}
+
+fn rgb(color: &Vector4<f32>) -> String {
+    let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(color.x),
+        channel(color.y),
+        channel(color.z)
+    )
+}
+
+fn fill(color: &Vector4<f32>) -> String {
+    format!("fill=\"{}\" fill-opacity=\"{}\"", rgb(color), color.w)
+}
+
+impl mesh_renderer::backend::RenderMesh for SvgRenderer {
+    fn queue_render(&mut self, transform: Matrix4<f32>, mesh: Mesh) {
+        self.queue.push((transform, mesh));
+    }
+}
+
+impl camera::backend::SetCamera for SvgRenderer {
+    fn set_camera(&mut self, transform: Matrix4<f32>, camera: &Camera) {
+        self.view = transform;
+        self.projection = camera.projection();
+        self.reversed_z = camera.reversed_z();
+    }
+}
+
+impl<D> Backend<D> for SvgRenderer
+where
+    D: Data,
+{
+    fn run(
+        mut self,
+        mut render_chain: RenderChain<Self, D>,
+        mut update_chain: UpdateChain<Self, D>,
+        mut data: D,
+    ) -> D {
+        let mut frames_run = 0;
+        while !self.quit_requested && self.max_frames.map_or(true, |max| frames_run < max) {
+            update_chain.update(&mut self, &mut data);
+            render_chain.render(&mut self, &data);
+            frames_run += 1;
+            if self.save_frame(frames_run).is_err() {
+                break;
+            }
+        }
+        data
+    }
+
+    fn quit(&mut self) {
+        self.quit_requested = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::backend::SetCamera;
+    use camera::Orthographic;
+    use geometry::{Triangle, Vertex};
+    use mesh_renderer::backend::RenderMesh;
+
+    fn renderer() -> SvgRenderer {
+        let mut renderer = SvgRenderer::new(40.0, 40.0);
+        let lens = Orthographic::extents(-20.0, 20.0, -20.0, 20.0).near(0.1).far(100.0);
+        renderer.set_camera(Matrix4::identity(), &Camera::new(lens));
+        renderer
+    }
+
+    fn triangle(z: f32, color: Vector4<f32>) -> Triangle {
+        let v = |x, y| Vertex::at(Point3::new(x, y, z)).color(color);
+        Triangle::new(v(-10.0, -10.0), v(10.0, -10.0), v(0.0, 10.0))
+    }
+
+    fn polygons(svg: &str) -> Vec<&str> {
+        svg.lines().filter(|l| l.contains("<polygon")).collect()
+    }
+
+    #[test]
+    fn draws_the_farthest_polygons_first() {
+        let mut renderer = renderer();
+        let near = triangle(-5.0, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        let far = triangle(-50.0, Vector4::new(0.0, 0.0, 1.0, 1.0));
+        let mesh = Mesh {
+            triangles: vec![near, far],
+        };
+        renderer.queue_render(Matrix4::identity(), mesh);
+
+        let svg = renderer.to_svg();
+        let polygons = polygons(&svg);
+        assert_eq!(polygons.len(), 2);
+        assert!(polygons[0].contains("#0000ff"));
+        assert!(polygons[1].contains("#ff0000"));
+    }
+
+    #[test]
+    fn back_faces_are_culled_unless_disabled() {
+        let color = Vector4::new(1.0, 1.0, 1.0, 1.0);
+        let front = triangle(-5.0, color);
+        let back = Triangle::new(front.v1, front.v3, front.v2);
+        let mesh = Mesh {
+            triangles: vec![front, back],
+        };
+
+        let mut renderer = renderer();
+        renderer.queue_render(Matrix4::identity(), mesh.clone());
+        assert_eq!(polygons(&renderer.to_svg()).len(), 1);
+
+        let mut renderer = renderer.cull_back_faces(false);
+        renderer.queue_render(Matrix4::identity(), mesh);
+        assert_eq!(polygons(&renderer.to_svg()).len(), 2);
+    }
+}
@@ -0,0 +1,234 @@
+use mursten::{Backend, Data, RenderChain, UpdateChain};
+use nalgebra::*;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+use super::camera::backend::{SetCamera, SetViewport};
+use super::camera::{Camera, Viewport};
+use super::geometry::Mesh;
+use super::input::backend::ResizeEventSource;
+use super::input::ResizeEvent;
+use super::light::backend::SetLights;
+use super::light::Light;
+use super::material::Material;
+use super::mesh_renderer::backend::{RenderMaterialMesh, RenderMesh};
+use super::software_renderer::{Framebuffer, SoftwareRenderer};
+
+const LUMINANCE_RAMP: &[u8] = b" .:-=+*#%@";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerminalMode {
+    // Characters picked by brightness, no escape codes at all.
+    Ascii,
+    // One pixel per character cell, painted with a 24-bit background color.
+    Ansi,
+    // Two pixels per character cell using the upper half block, so pixels come out about square.
+    HalfBlock,
+}
+
+// Draws meshes into a grid of `columns` by `rows` characters. Rasterization is done by a
+// `SoftwareRenderer`, this only turns its framebuffer into text.
+//
+// As a `Backend` it runs the updaters and renderers and presents each frame on the standard
+// output, until `quit` is called, `max_frames` frames were presented or the output fails.
+pub struct TerminalRenderer {
+    renderer: SoftwareRenderer,
+    columns: usize,
+    rows: usize,
+    mode: TerminalMode,
+    quit_requested: bool,
+    max_frames: Option<usize>,
+}
+
+impl TerminalRenderer {
+    pub fn new(columns: usize, rows: usize, mode: TerminalMode) -> Self {
+        let (width, height) = Self::pixel_size(columns, rows, mode);
+        TerminalRenderer {
+            renderer: SoftwareRenderer::new(width, height),
+            columns,
+            rows,
+            mode,
+            quit_requested: false,
+            max_frames: None,
+        }
+    }
+
+    // Stops `run` after this many frames, unless `quit` is called earlier.
+    pub fn max_frames(self, max_frames: usize) -> Self {
+        Self {
+            max_frames: Some(max_frames),
+            ..self
+        }
+    }
+
+    fn pixel_size(columns: usize, rows: usize, mode: TerminalMode) -> (usize, usize) {
+        match mode {
+            TerminalMode::HalfBlock => (columns, rows * 2),
+            TerminalMode::Ascii | TerminalMode::Ansi => (columns, rows),
+        }
+    }
+
+    // Character cells are about twice as tall as they are wide. Cameras should use this aspect
+    // ratio for the picture not to look stretched.
+    pub fn aspect_ratio(&self) -> f32 {
+        self.columns as f32 / (self.rows * 2) as f32
+    }
+
+    fn pixel_aspect(&self) -> f32 {
+        match self.mode {
+            TerminalMode::HalfBlock => 1.0,
+            TerminalMode::Ascii | TerminalMode::Ansi => 0.5,
+        }
+    }
+
+    pub fn resize(&mut self, columns: usize, rows: usize) {
+        let (width, height) = Self::pixel_size(columns, rows, self.mode);
+        self.columns = columns;
+        self.rows = rows;
+        self.renderer.resize(width, height);
+    }
+
+    pub fn software_renderer(&mut self) -> &mut SoftwareRenderer {
+        &mut self.renderer
+    }
+
+    // Draws the queued meshes and returns the frame as lines of text, without moving the cursor.
+    pub fn render_to_string(&mut self) -> String {
+        let mode = self.mode;
+        let framebuffer = self.renderer.render_frame();
+        match mode {
+            TerminalMode::Ascii => ascii(framebuffer),
+            TerminalMode::Ansi => ansi(framebuffer),
+            TerminalMode::HalfBlock => half_block(framebuffer),
+        }
+    }
+
+    // Draws the frame over the previous one, starting at the top left corner of the terminal.
+    pub fn present<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let frame = self.render_to_string();
+        out.write_all(b"\x1b[H")?;
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+fn luminance(p: [u8; 4]) -> f32 {
+    (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32) / 255.0
+}
+
+fn ascii(framebuffer: &Framebuffer) -> String {
+    let mut out = String::new();
+    for y in 0..framebuffer.height() {
+        for x in 0..framebuffer.width() {
+            let l = luminance(framebuffer.get(x, y));
+            let i = (l * (LUMINANCE_RAMP.len() - 1) as f32).round() as usize;
+            out.push(LUMINANCE_RAMP[i.min(LUMINANCE_RAMP.len() - 1)] as char);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn ansi(framebuffer: &Framebuffer) -> String {
+    let mut out = String::new();
+    for y in 0..framebuffer.height() {
+        for x in 0..framebuffer.width() {
+            let p = framebuffer.get(x, y);
+            write!(out, "\x1b[48;2;{};{};{}m ", p[0], p[1], p[2]).unwrap();
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+fn half_block(framebuffer: &Framebuffer) -> String {
+    let mut out = String::new();
+    for y in (0..framebuffer.height()).step_by(2) {
+        for x in 0..framebuffer.width() {
+            let top = framebuffer.get(x, y);
+            let bottom = if y + 1 < framebuffer.height() {
+                framebuffer.get(x, y + 1)
+            } else {
+                [0, 0, 0, 255]
+            };
+            write!(
+                out,
+                "\x1b[38;2;{};{};{};48;2;{};{};{}m\u{2580}",
+                top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+            ).unwrap();
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+impl RenderMesh for TerminalRenderer {
+    fn queue_render(&mut self, transform: Matrix4<f32>, mesh: Mesh) {
+        self.renderer.queue_render(transform, mesh);
+    }
+}
+
+impl RenderMaterialMesh for TerminalRenderer {
+    fn queue_render_material(&mut self, transform: Matrix4<f32>, mesh: Mesh, material: Material) {
+        self.renderer.queue_render_material(transform, mesh, material);
+    }
+}
+
+impl SetCamera for TerminalRenderer {
+    fn set_camera(&mut self, transform: Matrix4<f32>, camera: &Camera) {
+        self.renderer.set_camera(transform, camera);
+    }
+}
+
+impl SetViewport for TerminalRenderer {
+    fn set_viewport(&mut self, transform: Matrix4<f32>, camera: &Camera, viewport: Viewport) {
+        self.renderer.set_viewport(transform, camera, viewport);
+    }
+}
+
+// Sizes are given in pixels of the framebuffer, which are not square outside of half block mode.
+impl ResizeEventSource for TerminalRenderer {
+    fn drain_events(&mut self) -> Vec<ResizeEvent> {
+        let pixel_aspect = self.pixel_aspect();
+        self.renderer
+            .drain_events()
+            .into_iter()
+            .map(|e| e.pixel_aspect(pixel_aspect))
+            .collect()
+    }
+}
+
+impl SetLights for TerminalRenderer {
+    fn set_lights(&mut self, lights: Vec<Light>) {
+        self.renderer.set_lights(lights);
+    }
+}
+
+impl<D> Backend<D> for TerminalRenderer
+where
+    D: Data,
+{
+    fn run(
+        mut self,
+        mut render_chain: RenderChain<Self, D>,
+        mut update_chain: UpdateChain<Self, D>,
+        mut data: D,
+    ) -> D {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let mut frames_run = 0;
+        while !self.quit_requested && self.max_frames.map_or(true, |max| frames_run < max) {
+            update_chain.update(&mut self, &mut data);
+            render_chain.render(&mut self, &data);
+            if self.present(&mut out).is_err() {
+                break;
+            }
+            frames_run += 1;
+        }
+        data
+    }
+
+    fn quit(&mut self) {
+        self.quit_requested = true;
+    }
+}
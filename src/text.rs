@@ -0,0 +1,334 @@
+use nalgebra::*;
+
+use super::geometry::{Mesh, Triangle, Vertex};
+use super::mesh_renderer::IntoMesh;
+
+// Glyphs are drawn on a small grid, Hershey style: x grows to the right from 0, y grows upwards
+// with the baseline at 2, the x-height at 6, the cap height at 8 and descenders reaching 0.
+// Every glyph is a list of strokes separated by `|`, every stroke a list of `xy` digit pairs.
+const BASELINE: f32 = 2.0;
+const CAP_HEIGHT: f32 = 6.0;
+const LINE_HEIGHT: f32 = 10.0;
+const GLYPH_SPACING: f32 = 2.0;
+const SPACE_ADVANCE: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextLayout {
+    pub size: f32,
+    pub line_spacing: f32,
+    pub stroke_width: f32,
+    pub align: Align,
+    pub color: Vector4<f32>,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        TextLayout {
+            size: 1.0,
+            line_spacing: 1.0,
+            stroke_width: 0.1,
+            align: Align::Left,
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl TextLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn size(self, size: f32) -> Self {
+        Self { size, ..self }
+    }
+    pub fn line_spacing(self, line_spacing: f32) -> Self {
+        Self { line_spacing, ..self }
+    }
+    pub fn stroke_width(self, stroke_width: f32) -> Self {
+        Self { stroke_width, ..self }
+    }
+    pub fn align(self, align: Align) -> Self {
+        Self { align, ..self }
+    }
+    pub fn color(self, color: Vector4<f32>) -> Self {
+        Self { color, ..self }
+    }
+}
+
+pub struct Text {
+    pub content: String,
+    pub layout: TextLayout,
+    pub transform: Matrix4<f32>,
+}
+
+impl Text {
+    pub fn new(content: &str) -> Self {
+        Text {
+            content: content.to_string(),
+            layout: TextLayout::default(),
+            transform: Matrix4::identity(),
+        }
+    }
+    pub fn layout(self, layout: TextLayout) -> Self {
+        Self { layout, ..self }
+    }
+}
+
+impl IntoMesh for Text {
+    fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+    fn mesh(&self) -> Mesh {
+        layout(&self.content, &self.layout)
+    }
+}
+
+// The origin of the resulting mesh is on the baseline of the first line, at the left edge, center
+// or right edge of the text depending on the alignment. Following lines go down along -y. The
+// `size` of the layout is the height of the capital letters.
+pub fn layout(text: &str, layout: &TextLayout) -> Mesh {
+    let scale = layout.size / CAP_HEIGHT;
+    let half_width = layout.stroke_width * layout.size / 2.0;
+    let mut triangles = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let width = line_width(line);
+        let offset_x = match layout.align {
+            Align::Left => 0.0,
+            Align::Center => -width / 2.0,
+            Align::Right => -width,
+        };
+        let offset_y = -(line_number as f32) * LINE_HEIGHT * layout.line_spacing;
+
+        let mut pen = offset_x;
+        for c in line.chars() {
+            let g = Glyph::of(c);
+            for stroke in g.strokes() {
+                for segment in stroke.windows(2) {
+                    let from = Point2::new(pen + segment[0].x, offset_y + segment[0].y - BASELINE);
+                    let to = Point2::new(pen + segment[1].x, offset_y + segment[1].y - BASELINE);
+                    push_segment(
+                        &mut triangles,
+                        from * scale,
+                        to * scale,
+                        half_width,
+                        layout.color,
+                    );
+                }
+            }
+            pen += g.advance();
+        }
+    }
+
+    Mesh { triangles }
+}
+
+// Width of a single line of text in world units for a text of size 1.
+pub fn measure(line: &str) -> f32 {
+    line_width(line) / CAP_HEIGHT
+}
+
+fn line_width(line: &str) -> f32 {
+    let advance: f32 = line.chars().map(|c| Glyph::of(c).advance()).sum();
+    // The spacing after the last glyph does not count as part of the text. Spaces have none.
+    match line.chars().last() {
+        Some(c) if !Glyph::of(c).definition.is_empty() => advance - GLYPH_SPACING,
+        _ => advance,
+    }
+}
+
+fn push_segment(
+    triangles: &mut Vec<Triangle>,
+    from: Point2<f32>,
+    to: Point2<f32>,
+    half_width: f32,
+    color: Vector4<f32>,
+) {
+    let direction = to - from;
+    let length = direction.norm();
+    let (along, across) = if length > 0.0 {
+        let along = direction / length * half_width;
+        (along, Vector2::new(-along.y, along.x))
+    } else {
+        // Zero length strokes are dots
+        (Vector2::new(half_width, 0.0), Vector2::new(0.0, half_width))
+    };
+
+    // Extending each segment by half the stroke width closes the gaps at the joints
+    let from = from - along;
+    let to = to + along;
+    let v = |p: Point2<f32>| Vertex::from(p).color(color);
+    let (a, b, c, d) = (
+        v(from - across),
+        v(to - across),
+        v(to + across),
+        v(from + across),
+    );
+    triangles.push(Triangle::new(a, b, c));
+    triangles.push(Triangle::new(a, c, d));
+}
+
+struct Glyph {
+    definition: &'static str,
+}
+
+impl Glyph {
+    fn of(c: char) -> Self {
+        let definition = if c == ' ' {
+            ""
+        } else {
+            glyph_definition(c).or_else(|| glyph_definition('?')).unwrap()
+        };
+        Glyph { definition }
+    }
+
+    fn strokes(&self) -> Vec<Vec<Point2<f32>>> {
+        self.definition
+            .split('|')
+            .map(|stroke| {
+                stroke
+                    .split_whitespace()
+                    .map(|pair| {
+                        let mut digits = pair.chars().filter_map(|d| d.to_digit(10));
+                        let x = digits.next().unwrap_or(0) as f32;
+                        let y = digits.next().unwrap_or(0) as f32;
+                        Point2::new(x, y)
+                    })
+                    .collect()
+            })
+            .filter(|stroke: &Vec<Point2<f32>>| !stroke.is_empty())
+            .collect()
+    }
+
+    fn advance(&self) -> f32 {
+        if self.definition.is_empty() {
+            return SPACE_ADVANCE;
+        }
+        let max_x = self.strokes()
+            .iter()
+            .flat_map(|s| s.iter())
+            .fold(0.0_f32, |m, p| m.max(p.x));
+        max_x + GLYPH_SPACING
+    }
+}
+
+fn glyph_definition(c: char) -> Option<&'static str> {
+    let d = match c {
+        '!' => "08 04|02 03",
+        '"' => "08 06|28 26",
+        '#' => "12 18|32 38|04 44|06 46",
+        '$' => "46 37 17 06 15 35 44 33 13 04|28 22",
+        '%' => "02 48|07 08 18 17 07|33 34 44 43 33",
+        '&' => "42 05 06 17 26 14 03 12 22 44",
+        '\'' => "08 06",
+        '(' => "18 06 04 12",
+        ')' => "08 16 14 02",
+        '*' => "04 46|06 44|23 27",
+        '+' => "23 27|05 45",
+        ',' => "13 12 01",
+        '-' => "05 45",
+        '.' => "02 12",
+        '/' => "02 48",
+        '0' => "12 03 07 18 38 47 43 32 12",
+        '1' => "17 28 22|12 32",
+        '2' => "07 18 38 47 46 02 42",
+        '3' => "07 18 38 47 46 35 15|35 44 43 32 12 03",
+        '4' => "32 38 04 44",
+        '5' => "48 08 05 35 44 43 32 02",
+        '6' => "47 38 18 07 03 12 32 43 44 35 15 04",
+        '7' => "08 48 22",
+        '8' => "15 06 07 18 38 47 46 35 15 04 03 12 32 43 44 35",
+        '9' => "46 35 15 06 07 18 38 47 43 32 12 03",
+        ':' => "03 13|06 16",
+        ';' => "13 02|06 16",
+        '<' => "47 05 43",
+        '=' => "04 44|06 46",
+        '>' => "07 45 03",
+        '?' => "07 18 38 47 46 25 24|22 23",
+        '@' => "42 12 03 07 18 38 47 43 33 24 25 36 46",
+        'A' => "02 28 42|15 35",
+        'B' => "02 08 38 47 46 35 05|35 44 43 32 02",
+        'C' => "47 38 18 07 03 12 32 43",
+        'D' => "02 08 38 47 43 32 02",
+        'E' => "42 02 08 48|05 35",
+        'F' => "02 08 48|05 35",
+        'G' => "47 38 18 07 03 12 32 43 45 25",
+        'H' => "02 08|42 48|05 45",
+        'I' => "08 28|18 12|02 22",
+        'J' => "48 43 32 12 03 04",
+        'K' => "02 08|48 04|15 42",
+        'L' => "08 02 42",
+        'M' => "02 08 25 48 42",
+        'N' => "02 08 42 48",
+        'O' => "12 03 07 18 38 47 43 32 12",
+        'P' => "02 08 38 47 46 35 05",
+        'Q' => "12 03 07 18 38 47 43 32 12|24 41",
+        'R' => "02 08 38 47 46 35 05|25 42",
+        'S' => "47 38 18 07 06 15 35 44 43 32 12 03",
+        'T' => "08 48|28 22",
+        'U' => "08 03 12 32 43 48",
+        'V' => "08 22 48",
+        'W' => "08 12 25 32 48",
+        'X' => "02 48|08 42",
+        'Y' => "08 25 48|25 22",
+        'Z' => "08 48 02 42",
+        '[' => "18 08 02 12",
+        '\\' => "08 42",
+        ']' => "08 18 12 02",
+        '^' => "06 28 46",
+        '_' => "01 41",
+        '`' => "08 17",
+        'a' => "46 42|45 36 16 05 03 12 32 43",
+        'b' => "08 02|03 12 32 43 45 36 16 05",
+        'c' => "45 36 16 05 03 12 32 43",
+        'd' => "48 42|43 32 12 03 05 16 36 45",
+        'e' => "04 44 45 36 16 05 03 12 32 43",
+        'f' => "38 28 17 12|06 26",
+        'g' => "46 41 30 10 01|45 36 16 05 03 12 32 43",
+        'h' => "08 02|05 16 36 45 42",
+        'i' => "06 02|07 08",
+        'j' => "16 11 00|17 18",
+        'k' => "08 02|36 03|14 32",
+        'l' => "08 03 12",
+        'm' => "06 02|05 16 25 22|25 36 45 42",
+        'n' => "06 02|05 16 36 45 42",
+        'o' => "12 03 05 16 36 45 43 32 12",
+        'p' => "06 00|05 16 36 45 43 32 12 03",
+        'q' => "46 40|45 36 16 05 03 12 32 43",
+        'r' => "06 02|05 16 36",
+        's' => "45 36 16 05 14 34 43 32 12 03",
+        't' => "17 13 22 32|06 36",
+        'u' => "06 03 12 32 43|46 42",
+        'v' => "06 22 46",
+        'w' => "06 12 24 32 46",
+        'x' => "06 42|02 46",
+        'y' => "06 33|46 20 10",
+        'z' => "06 46 02 42",
+        '{' => "28 17 16 05 14 13 22",
+        '|' => "08 00",
+        '}' => "08 17 16 25 14 13 02",
+        '~' => "05 16 35 46",
+        _ => return None,
+    };
+    Some(d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_spaces_add_their_whole_advance() {
+        let spaced = (GLYPH_SPACING + SPACE_ADVANCE) / CAP_HEIGHT;
+        assert_eq!(measure(""), 0.0);
+        assert_eq!(measure(" "), SPACE_ADVANCE / CAP_HEIGHT);
+        assert!((measure("ab ") - (measure("ab") + spaced)).abs() < 1e-6);
+        assert!((measure("ab  ") - (measure("ab ") + SPACE_ADVANCE / CAP_HEIGHT)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,918 @@
+use nalgebra::*;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use super::geometry::{Mesh, Vertex};
+use super::image_output;
+use super::software_renderer::{Framebuffer, Sampler};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pixels {
+    // Four bytes per pixel.
+    Rgba8(Vec<u8>),
+    Float(Vec<Vector4<f32>>),
+}
+
+// Pixels are stored in rows from top to bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Pixels,
+}
+
+impl Image {
+    pub fn from_rgba8(width: usize, height: usize, pixels: Vec<u8>) -> Self {
+        assert_eq!(pixels.len(), width * height * 4);
+        Image {
+            width,
+            height,
+            pixels: Pixels::Rgba8(pixels),
+        }
+    }
+    pub fn from_floats(width: usize, height: usize, pixels: Vec<Vector4<f32>>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Image {
+            width,
+            height,
+            pixels: Pixels::Float(pixels),
+        }
+    }
+    pub fn from_framebuffer(framebuffer: &Framebuffer) -> Self {
+        Self::from_rgba8(
+            framebuffer.width(),
+            framebuffer.height(),
+            framebuffer.as_bytes().to_vec(),
+        )
+    }
+
+    // Picks the decoder from the file extension: png, ppm or tga.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let extension = path.as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        let file = BufReader::new(File::open(&path)?);
+        match extension.as_ref().map(|e| e.as_str()) {
+            Some("png") => read_png(file),
+            Some("ppm") => image_output::read_ppm(file).map(|fb| Self::from_framebuffer(&fb)),
+            Some("tga") => read_tga(file),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unknown image file extension",
+            )),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn pixels(&self) -> &Pixels {
+        &self.pixels
+    }
+
+    // Color of a pixel with channels between zero and one.
+    pub fn get(&self, x: usize, y: usize) -> Vector4<f32> {
+        let i = y * self.width + x;
+        match self.pixels {
+            Pixels::Rgba8(ref bytes) => {
+                let p = &bytes[i * 4..i * 4 + 4];
+                Vector4::new(p[0] as f32, p[1] as f32, p[2] as f32, p[3] as f32) / 255.0
+            }
+            Pixels::Float(ref colors) => colors[i],
+        }
+    }
+
+    pub fn to_float(&self) -> Self {
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                pixels.push(self.get(x, y));
+            }
+        }
+        Self::from_floats(self.width, self.height, pixels)
+    }
+
+    pub fn to_rgba8(&self) -> Self {
+        let mut pixels = Vec::with_capacity(self.width * self.height * 4);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let color = self.get(x, y);
+                pixels.extend(color.iter().map(|c| (c.max(0.0).min(1.0) * 255.0).round() as u8));
+            }
+        }
+        Self::from_rgba8(self.width, self.height, pixels)
+    }
+
+    // Half the size in each direction, averaging blocks of two by two pixels. Odd sizes round
+    // down and the last row or column is blended into the previous one.
+    pub fn downsample(&self) -> Self {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let xs = span(x, width, self.width);
+                let ys = span(y, height, self.height);
+                let mut sum = Vector4::zeros();
+                for &sy in &ys {
+                    for &sx in &xs {
+                        sum += self.get(sx, sy);
+                    }
+                }
+                pixels.push(sum / (xs.len() * ys.len()) as f32);
+            }
+        }
+        let image = Self::from_floats(width, height, pixels);
+        match self.pixels {
+            Pixels::Rgba8(_) => image.to_rgba8(),
+            Pixels::Float(_) => image,
+        }
+    }
+}
+
+// Source pixels that are averaged into pixel `i` when shrinking `from` pixels into `to`.
+fn span(i: usize, to: usize, from: usize) -> Vec<usize> {
+    if to == from {
+        return vec![i];
+    }
+    let start = i * 2;
+    let end = if i + 1 == to { from } else { start + 2 };
+    (start..end).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+// What happens to texture coordinates outside of 0..1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+// An image with its sampling settings. Texture coordinates (0, 0) are the top left corner of
+// the image and (1, 1) the bottom right one.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    levels: Vec<Image>,
+    pub filter: Filter,
+    pub wrap: Wrap,
+}
+
+impl Texture {
+    pub fn new(image: Image) -> Self {
+        Texture {
+            levels: vec![image],
+            filter: Filter::Bilinear,
+            wrap: Wrap::Repeat,
+        }
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Image::load(path).map(Self::new)
+    }
+    pub fn filter(self, filter: Filter) -> Self {
+        Self { filter, ..self }
+    }
+    pub fn wrap(self, wrap: Wrap) -> Self {
+        Self { wrap, ..self }
+    }
+    // Generates every level down to one pixel.
+    pub fn mipmaps(mut self) -> Self {
+        self.levels.truncate(1);
+        loop {
+            let next = {
+                let last = self.levels.last().unwrap();
+                if last.width == 1 && last.height == 1 {
+                    break;
+                }
+                last.downsample()
+            };
+            self.levels.push(next);
+        }
+        self
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.levels[0]
+    }
+    pub fn levels(&self) -> &[Image] {
+        &self.levels
+    }
+
+    pub fn sample(&self, uv: Point2<f32>) -> Vector4<f32> {
+        self.sample_image(&self.levels[0], uv)
+    }
+
+    // Samples a blend of the two mipmap levels closest to `lod`, where 0 is the full size image
+    // and every unit halves it. Without mipmaps this is the same as `sample`.
+    pub fn sample_lod(&self, uv: Point2<f32>, lod: f32) -> Vector4<f32> {
+        let max = (self.levels.len() - 1) as f32;
+        let lod = lod.max(0.0).min(max);
+        let level = lod.floor() as usize;
+        let t = lod - level as f32;
+        let color = self.sample_image(&self.levels[level], uv);
+        if t > 0.0 {
+            color * (1.0 - t) + self.sample_image(&self.levels[level + 1], uv) * t
+        } else {
+            color
+        }
+    }
+
+    // Level of detail for a texture coordinate that changes by `du` and `dv` between neighbouring
+    // pixels on screen.
+    pub fn lod(&self, du: Vector2<f32>, dv: Vector2<f32>) -> f32 {
+        let size = Vector2::new(self.levels[0].width as f32, self.levels[0].height as f32);
+        let footprint = du.component_mul(&size)
+            .norm()
+            .max(dv.component_mul(&size).norm());
+        if footprint > 1.0 {
+            footprint.log2()
+        } else {
+            0.0
+        }
+    }
+
+    // For `SoftwareRenderer::texture` and `SoftwareRenderer::add_texture`.
+    pub fn into_sampler(self) -> Sampler {
+        Box::new(move |uv| self.sample(uv))
+    }
+
+    // Multiplies the vertex colors by the texture at their texture coordinates.
+    pub fn bake(&self, mesh: Mesh) -> Mesh {
+        let bake_vertex = |v: Vertex| Vertex {
+            color: v.color.component_mul(&self.sample(v.texture)),
+            ..v
+        };
+        Mesh {
+            triangles: mesh.triangles
+                .into_iter()
+                .map(|mut t| {
+                    t.v1 = bake_vertex(t.v1);
+                    t.v2 = bake_vertex(t.v2);
+                    t.v3 = bake_vertex(t.v3);
+                    t
+                })
+                .collect(),
+        }
+    }
+
+    fn sample_image(&self, image: &Image, uv: Point2<f32>) -> Vector4<f32> {
+        let x = uv.x * image.width as f32;
+        let y = uv.y * image.height as f32;
+        match self.filter {
+            Filter::Nearest => image.get(
+                self.texel(x.floor() as i64, image.width),
+                self.texel(y.floor() as i64, image.height),
+            ),
+            Filter::Bilinear => {
+                // Texel centers sit at half coordinates
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let (xa, xb) = (self.texel(x0, image.width), self.texel(x0 + 1, image.width));
+                let (ya, yb) = (self.texel(y0, image.height), self.texel(y0 + 1, image.height));
+                let top = image.get(xa, ya) * (1.0 - tx) + image.get(xb, ya) * tx;
+                let bottom = image.get(xa, yb) * (1.0 - tx) + image.get(xb, yb) * tx;
+                top * (1.0 - ty) + bottom * ty
+            }
+        }
+    }
+
+    fn texel(&self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self.wrap {
+            Wrap::Repeat => (i % size + size) % size,
+            Wrap::Clamp => i.max(0).min(size - 1),
+            Wrap::Mirror => {
+                let period = (i % (size * 2) + size * 2) % (size * 2);
+                if period < size {
+                    period
+                } else {
+                    size * 2 - 1 - period
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Non interlaced PNG with 8 or 16 bits per channel, in gray, gray with alpha, RGB, RGBA or
+// 8 bit palette color. 16 bit channels are reduced to 8 bits.
+pub fn read_png<R: Read>(mut input: R) -> io::Result<Image> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    if bytes.len() < 8 || &bytes[..8] != b"\x89PNG\r\n\x1a\n" {
+        return Err(invalid("Not a PNG file"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut transparency = Vec::new();
+    let mut compressed = Vec::new();
+    let mut position = 8;
+    while position + 8 <= bytes.len() {
+        let length = be32(&bytes[position..]) as usize;
+        let kind = &bytes[position + 4..position + 8];
+        let start = position + 8;
+        if start + length + 4 > bytes.len() {
+            return Err(invalid("Truncated PNG chunk"));
+        }
+        let data = &bytes[start..start + length];
+        match kind {
+            b"IHDR" if length == 13 => header = Some(data.to_vec()),
+            b"PLTE" => palette = data.to_vec(),
+            b"tRNS" => transparency = data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        position = start + length + 4;
+    }
+
+    let header = header.ok_or_else(|| invalid("Missing PNG header"))?;
+    let width = be32(&header[0..]) as usize;
+    let height = be32(&header[4..]) as usize;
+    if width == 0 || height == 0 {
+        return Err(invalid("PNG image without pixels"));
+    }
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if interlace != 0 {
+        return Err(invalid("Interlaced PNG is not supported"));
+    }
+    let channels = match (color_type, depth) {
+        (0, 8) | (0, 16) => 1,
+        (2, 8) | (2, 16) => 3,
+        (3, 8) => 1,
+        (4, 8) | (4, 16) => 2,
+        (6, 8) | (6, 16) => 4,
+        _ => return Err(invalid("Unsupported PNG color type or bit depth")),
+    };
+    let pixel_bytes = channels * depth as usize / 8;
+    let row_bytes = width * pixel_bytes;
+
+    let raw = zlib_decompress(&compressed)?;
+    if raw.len() < (row_bytes + 1) * height {
+        return Err(invalid("Truncated PNG image data"));
+    }
+    let mut rows = vec![0; row_bytes * height];
+    for y in 0..height {
+        let filter = raw[y * (row_bytes + 1)];
+        let line = &raw[y * (row_bytes + 1) + 1..(y + 1) * (row_bytes + 1)];
+        let (previous, current) = rows.split_at_mut(y * row_bytes);
+        let above = if y > 0 {
+            &previous[(y - 1) * row_bytes..]
+        } else {
+            &[][..]
+        };
+        unfilter(filter, line, above, &mut current[..row_bytes], pixel_bytes)?;
+    }
+
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    let step = depth as usize / 8;
+    for p in rows.chunks(pixel_bytes) {
+        // High byte of each channel
+        let c = |i: usize| p[i * step];
+        let rgba = match color_type {
+            0 => [c(0), c(0), c(0), 255],
+            2 => [c(0), c(1), c(2), 255],
+            3 => {
+                let i = c(0) as usize;
+                if i * 3 + 3 > palette.len() {
+                    return Err(invalid("PNG palette index out of range"));
+                }
+                let alpha = transparency.get(i).cloned().unwrap_or(255);
+                [palette[i * 3], palette[i * 3 + 1], palette[i * 3 + 2], alpha]
+            }
+            4 => [c(0), c(0), c(0), c(1)],
+            _ => [c(0), c(1), c(2), c(3)],
+        };
+        pixels.extend_from_slice(&rgba);
+    }
+    Ok(Image::from_rgba8(width, height, pixels))
+}
+
+fn be32(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+fn unfilter(
+    filter: u8,
+    line: &[u8],
+    above: &[u8],
+    out: &mut [u8],
+    pixel_bytes: usize,
+) -> io::Result<()> {
+    for i in 0..line.len() {
+        let a = if i >= pixel_bytes { out[i - pixel_bytes] } else { 0 };
+        let b = above.get(i).cloned().unwrap_or(0);
+        let c = if i >= pixel_bytes {
+            above.get(i - pixel_bytes).cloned().unwrap_or(0)
+        } else {
+            0
+        };
+        let predicted = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return Err(invalid("Unknown PNG filter")),
+        };
+        out[i] = line[i].wrapping_add(predicted);
+    }
+    Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Uncompressed, RLE, true color and gray TGA with 8, 24 or 32 bits per pixel.
+pub fn read_tga<R: Read>(mut input: R) -> io::Result<Image> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    if bytes.len() < 18 {
+        return Err(invalid("Truncated TGA header"));
+    }
+    let id_length = bytes[0] as usize;
+    let color_map_type = bytes[1];
+    let image_type = bytes[2];
+    let width = (bytes[12] as usize) | (bytes[13] as usize) << 8;
+    let height = (bytes[14] as usize) | (bytes[15] as usize) << 8;
+    let bits = bytes[16];
+    let top_to_bottom = bytes[17] & 0x20 != 0;
+
+    if width == 0 || height == 0 {
+        return Err(invalid("TGA image without pixels"));
+    }
+    if color_map_type != 0 {
+        return Err(invalid("Color mapped TGA is not supported"));
+    }
+    let (gray, rle) = match image_type {
+        2 => (false, false),
+        3 => (true, false),
+        10 => (false, true),
+        11 => (true, true),
+        _ => return Err(invalid("Unsupported TGA image type")),
+    };
+    let pixel_bytes = match (gray, bits) {
+        (true, 8) => 1,
+        (false, 24) => 3,
+        (false, 32) => 4,
+        _ => return Err(invalid("Unsupported TGA pixel depth")),
+    };
+
+    let data = &bytes[(18 + id_length).min(bytes.len())..];
+    let count = width * height;
+    let mut raw = Vec::with_capacity(count * pixel_bytes);
+    if rle {
+        let mut i = 0;
+        while raw.len() < count * pixel_bytes {
+            let packet = *data.get(i).ok_or_else(|| invalid("Truncated TGA image data"))?;
+            let n = (packet & 0x7f) as usize + 1;
+            i += 1;
+            if packet & 0x80 != 0 {
+                let pixel = data.get(i..i + pixel_bytes)
+                    .ok_or_else(|| invalid("Truncated TGA image data"))?;
+                for _ in 0..n {
+                    raw.extend_from_slice(pixel);
+                }
+                i += pixel_bytes;
+            } else {
+                let pixels = data.get(i..i + n * pixel_bytes)
+                    .ok_or_else(|| invalid("Truncated TGA image data"))?;
+                raw.extend_from_slice(pixels);
+                i += n * pixel_bytes;
+            }
+        }
+        raw.truncate(count * pixel_bytes);
+    } else {
+        let pixels = data.get(..count * pixel_bytes)
+            .ok_or_else(|| invalid("Truncated TGA image data"))?;
+        raw.extend_from_slice(pixels);
+    }
+
+    let mut pixels = vec![0; count * 4];
+    for (i, p) in raw.chunks(pixel_bytes).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let y = if top_to_bottom { y } else { height - 1 - y };
+        // Colors are stored as BGR(A)
+        let rgba = match pixel_bytes {
+            1 => [p[0], p[0], p[0], 255],
+            3 => [p[2], p[1], p[0], 255],
+            _ => [p[2], p[1], p[0], p[3]],
+        };
+        let o = (y * width + x) * 4;
+        pixels[o..o + 4].copy_from_slice(&rgba);
+    }
+    Ok(Image::from_rgba8(width, height, pixels))
+}
+
+// Inflate, as described in RFC 1950 and RFC 1951. Checksums are not verified.
+fn zlib_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || ((data[0] as u16) << 8 | data[1] as u16) % 31 != 0
+    {
+        return Err(invalid("Invalid zlib header"));
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(invalid("Preset zlib dictionaries are not supported"));
+    }
+    let mut bits = BitReader {
+        data: &data[2..],
+        position: 0,
+        bit: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                bits.align();
+                let length = bits.read_byte()? as usize | (bits.read_byte()? as usize) << 8;
+                bits.read_byte()?;
+                bits.read_byte()?;
+                for _ in 0..length {
+                    out.push(bits.read_byte()?);
+                }
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                for (i, l) in lengths.iter_mut().enumerate() {
+                    *l = match i {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(invalid("Invalid deflate block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data
+                .get(self.position)
+                .ok_or_else(|| invalid("Truncated deflate stream"))?;
+            value |= ((byte as u32 >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Ok(value)
+    }
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.position += 1;
+        }
+    }
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.read(8).map(|b| b as u8)
+    }
+}
+
+// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..16 {
+            for (symbol, &l) in lengths.iter().enumerate() {
+                if l as usize == length {
+                    symbols.push(symbol as u16);
+                }
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for length in 1..16 {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("Invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn dynamic_tables(bits: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_count = bits.read(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_count) {
+        code_lengths[i] = bits.read(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match codes.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("Repeated length without a previous one"))?;
+                (previous, 3 + bits.read(2)?)
+            }
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(invalid("Too many code lengths"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let i = symbol - 257;
+            if i >= LENGTH_BASE.len() {
+                return Err(invalid("Invalid deflate length"));
+            }
+            let length = LENGTH_BASE[i] as usize + bits.read(LENGTH_EXTRA[i] as u32)? as usize;
+            let d = distances.decode(bits)? as usize;
+            if d >= DISTANCE_BASE.len() {
+                return Err(invalid("Invalid deflate distance"));
+            }
+            let distance =
+                DISTANCE_BASE[d] as usize + bits.read(DISTANCE_EXTRA[d] as u32)? as usize;
+            if distance > out.len() {
+                return Err(invalid("Deflate distance too far back"));
+            }
+            let start = out.len() - distance;
+            for k in 0..length {
+                let byte = out[start + k];
+                out.push(byte);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard() -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+                framebuffer.set(x, y, [v, 40 * x as u8, 100 * y as u8, 200 + x as u8]);
+            }
+        }
+        framebuffer
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let framebuffer = checkerboard();
+        let mut png = Vec::new();
+        image_output::write_png(&mut png, &framebuffer).unwrap();
+        let image = read_png(&png[..]).unwrap();
+        assert_eq!(image, Image::from_framebuffer(&framebuffer));
+    }
+
+    #[test]
+    fn truncated_png_is_an_error() {
+        let mut png = Vec::new();
+        image_output::write_png(&mut png, &checkerboard()).unwrap();
+        for &length in &[4, 20, png.len() / 2, png.len() - 20] {
+            assert!(read_png(&png[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn stored_block() {
+        let stored = [
+            0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27,
+        ];
+        assert_eq!(zlib_decompress(&stored).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn fixed_huffman_block() {
+        let fixed = [
+            0x78, 0x01, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00, 0x3a, 0x2e,
+            0x06, 0x7d,
+        ];
+        assert_eq!(zlib_decompress(&fixed).unwrap(), &b"hello hello hello"[..]);
+        for length in 2..fixed.len() - 4 {
+            assert!(zlib_decompress(&fixed[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn dynamic_huffman_block() {
+        let dynamic = [
+            0x78, 0xda, 0x5d, 0xcb, 0xc1, 0x0d, 0x80, 0x20, 0x0c, 0x05, 0xd0, 0x55, 0xfe, 0x02,
+            0x3a, 0x85, 0x77, 0x66, 0x28, 0x50, 0x90, 0x44, 0x4a, 0xd2, 0x7e, 0xe2, 0xfa, 0xde,
+            0x7d, 0xf7, 0x97, 0xb6, 0xe3, 0x12, 0x0a, 0x82, 0xbe, 0x0b, 0x03, 0x59, 0xcb, 0x9a,
+            0x8a, 0x3e, 0xc4, 0x88, 0x5b, 0xac, 0x1e, 0xaf, 0x0f, 0x52, 0x0d, 0x59, 0x7a, 0x60,
+            0x35, 0xb4, 0xa1, 0x4f, 0x8d, 0x13, 0xe9, 0x57, 0x3f, 0x0e, 0x9d, 0x1b, 0x5b,
+        ];
+        let text = "Our Data structs become giant hand-written bags of fields. Our Data structs";
+        assert_eq!(zlib_decompress(&dynamic).unwrap(), text.as_bytes());
+        assert!(zlib_decompress(&dynamic[..dynamic.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn tga_uncompressed_and_run_length_encoded() {
+        // 2x2, 24 bits, rows from bottom to top
+        let mut tga = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 24, 0];
+        tga.extend_from_slice(&[255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+        let image = read_tga(&tga[..]).unwrap();
+        // Red and white on top, blue and green below
+        let expected = vec![
+            255, 0, 0, 255, 255, 255, 255, 255, 0, 0, 255, 255, 0, 255, 0, 255,
+        ];
+        assert_eq!(image, Image::from_rgba8(2, 2, expected));
+
+        // 3x1, 32 bits, top to bottom: a run of two pixels, then one raw pixel
+        let mut rle = vec![0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 1, 0, 32, 0x20];
+        rle.extend_from_slice(&[0x81, 10, 20, 30, 40, 0x00, 50, 60, 70, 80]);
+        let image = read_tga(&rle[..]).unwrap();
+        let expected = vec![30, 20, 10, 40, 30, 20, 10, 40, 70, 60, 50, 80];
+        assert_eq!(image, Image::from_rgba8(3, 1, expected));
+
+        assert!(read_tga(&rle[..rle.len() - 2]).is_err());
+        assert!(read_tga(&tga[..10]).is_err());
+    }
+
+    #[test]
+    fn images_without_pixels_are_an_error() {
+        let mut tga = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 24, 0];
+        assert!(read_tga(&tga[..]).is_err());
+        tga[12] = 2;
+        tga[14] = 0;
+        assert!(read_tga(&tga[..]).is_err());
+
+        let mut png = Vec::new();
+        image_output::write_png(&mut png, &Framebuffer::new(0, 2)).unwrap();
+        assert!(read_png(&png[..]).is_err());
+    }
+
+    fn gray(values: &[f32], width: usize) -> Image {
+        let pixels = values.iter().map(|&v| Vector4::new(v, v, v, 1.0)).collect();
+        Image::from_floats(width, values.len() / width, pixels)
+    }
+
+    fn close(a: Vector4<f32>, b: f32) -> bool {
+        (a.x - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn nearest_and_bilinear_filtering() {
+        let texture = Texture::new(gray(&[0.0, 1.0], 2)).wrap(Wrap::Clamp);
+        // A quarter of the way is the center of the first texel
+        assert!(close(texture.sample(Point2::new(0.25, 0.5)), 0.0));
+        assert!(close(texture.sample(Point2::new(0.5, 0.5)), 0.5));
+        assert!(close(texture.sample(Point2::new(0.625, 0.5)), 0.75));
+
+        let nearest = texture.filter(Filter::Nearest);
+        assert!(close(nearest.sample(Point2::new(0.45, 0.5)), 0.0));
+        assert!(close(nearest.sample(Point2::new(0.55, 0.5)), 1.0));
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let texture = Texture::new(gray(&[0.0, 0.25, 0.5, 1.0], 4)).filter(Filter::Nearest);
+        let at = |texture: &Texture, u| texture.sample(Point2::new(u, 0.5)).x;
+        let repeat = texture.clone().wrap(Wrap::Repeat);
+        assert_eq!((at(&repeat, -0.125), at(&repeat, 1.125)), (1.0, 0.0));
+        let clamp = texture.clone().wrap(Wrap::Clamp);
+        assert_eq!((at(&clamp, -0.125), at(&clamp, 1.125)), (0.0, 1.0));
+        let mirror = texture.wrap(Wrap::Mirror);
+        assert_eq!((at(&mirror, -0.125), at(&mirror, 1.125)), (0.0, 1.0));
+        assert_eq!((at(&mirror, -0.375), at(&mirror, 1.375)), (0.25, 0.5));
+    }
+
+    #[test]
+    fn mipmaps_average_down_to_one_pixel() {
+        let texture = Texture::new(gray(&[0.0, 0.5, 1.0, 0.5, 0.0, 0.5, 1.0, 0.5], 4)).mipmaps();
+        let sizes: Vec<(usize, usize)> = texture
+            .levels()
+            .iter()
+            .map(|level| (level.width(), level.height()))
+            .collect();
+        assert_eq!(sizes, vec![(4, 2), (2, 1), (1, 1)]);
+        assert!(close(texture.levels()[1].get(0, 0), 0.25));
+        assert!(close(texture.levels()[1].get(1, 0), 0.75));
+        assert!(close(texture.levels()[2].get(0, 0), 0.5));
+
+        let center = Point2::new(0.5, 0.5);
+        let texture = texture.filter(Filter::Nearest);
+        assert!(close(texture.sample_lod(Point2::new(0.1, 0.5), 1.0), 0.25));
+        assert!(close(texture.sample_lod(Point2::new(0.1, 0.5), 1.5), 0.375));
+        // Beyond the last level the smallest one is used
+        assert!(close(texture.sample_lod(center, 9.0), 0.5));
+        assert_eq!(texture.sample_lod(center, -1.0), texture.sample(center));
+    }
+
+    #[test]
+    fn lod_follows_the_texel_footprint() {
+        let texture = Texture::new(gray(&[0.0; 64 * 32], 64));
+        let lod = |du: f32, dv: f32| texture.lod(Vector2::new(du, 0.0), Vector2::new(0.0, dv));
+        assert_eq!(lod(1.0 / 128.0, 1.0 / 64.0), 0.0);
+        assert!((lod(4.0 / 64.0, 1.0 / 64.0) - 2.0).abs() < 1e-5);
+        assert!((lod(1.0 / 64.0, 8.0 / 32.0) - 3.0).abs() < 1e-5);
+    }
+}
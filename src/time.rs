@@ -116,3 +116,31 @@ where
         self.last_system_time = system_time;
     }
 }
+
+// Ticks with a constant delta instead of the elapsed system time, so the data advances at the
+// same pace no matter how long each frame takes. Useful for offline rendering.
+pub struct FixedClockUpdater {
+    delta: Duration,
+}
+
+impl FixedClockUpdater {
+    pub fn new(frame_rate: f32) -> FixedClockUpdater {
+        let nanos = (1_000_000_000.0 / frame_rate as f64).round() as u64;
+        FixedClockUpdater {
+            delta: Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32),
+        }
+    }
+}
+
+impl<B, D> Updater<B, D> for FixedClockUpdater
+where
+    D: Data + OnTick,
+{
+    fn update(&mut self, _: &mut B, data: &mut D) {
+        let tick = Tick {
+            system_time: SystemTime::now(),
+            delta: self.delta,
+        };
+        data.on_tick(tick);
+    }
+}